@@ -0,0 +1,279 @@
+use std::sync::Arc;
+use bytes::{Buf, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+use crate::extension::Registries;
+use crate::hsms::{DecodeMode, FrameError, HSMSMessage, HSMSMessageRef, Quirks};
+use crate::utils::Error;
+use crate::wire;
+
+/*
+ * @brief HsmsCodec
+ * tokio_util的帧编解码，配合Framed直接用在TcpStream上：
+ *   let mut framed = Framed::new(stream,HsmsCodec::new());
+ * 先读4字节长度，缓冲区攒够一整帧后解析为HSMSMessage
+ * 长度小于10（不足一个消息头）或超过max_length时返回错误，
+ * 此时数据流已经不可信，调用方应断开连接
+ * Quirks::little_endian_length只在这里影响分帧，直接调用from_bytes*或find_next_frame时不会按小端读取长度
+ * DecodeMode::Strict时每帧都经过registries校验，有违规项返回InvalidFrame；默认Lenient不校验
+ */
+#[derive(Debug,Clone)]
+pub struct HsmsCodec{
+    max_length:u32,
+    quirks:Quirks,
+    mode:DecodeMode,
+    registries:Arc<Registries>,
+}
+
+//默认允许的最大消息长度（不含4字节长度前缀）
+pub const DEFAULT_MAX_LENGTH:u32 = 16*1024*1024;
+
+impl Default for HsmsCodec {
+    fn default() -> Self {
+        HsmsCodec{
+            max_length:DEFAULT_MAX_LENGTH,
+            quirks:Quirks::default(),
+            mode:DecodeMode::default(),
+            registries:Arc::default(),
+        }
+    }
+}
+
+impl HsmsCodec {
+    pub fn new()->HsmsCodec{
+        HsmsCodec::default()
+    }
+
+    pub fn with_max_length(mut self,max_length:u32)->HsmsCodec{
+        self.max_length = max_length;
+        self
+    }
+
+    pub fn with_quirks(mut self,quirks:Quirks)->HsmsCodec{
+        self.quirks = quirks;
+        self
+    }
+
+    pub fn with_mode(mut self,mode:DecodeMode)->HsmsCodec{
+        self.mode = mode;
+        self
+    }
+
+    //多个连接可以共享同一份注册表
+    pub fn with_registries(mut self,registries:Arc<Registries>)->HsmsCodec{
+        self.registries = registries;
+        self
+    }
+
+    pub fn max_length(&self)->u32{
+        self.max_length
+    }
+
+    pub fn mode(&self)->DecodeMode{
+        self.mode
+    }
+
+    pub fn registries(&self)->&Arc<Registries>{
+        &self.registries
+    }
+}
+
+impl Decoder for HsmsCodec {
+    type Item = HSMSMessage;
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some(length) = src.first_chunk::<{wire::LENGTH_PREFIX_LEN}>() else {
+            return Ok(None);
+        };
+        let message_length = if self.quirks.little_endian_length {
+            u32::from_le_bytes(*length)
+        } else {
+            wire::decode_length(length)
+        };
+        if message_length<wire::HEADER_LEN as u32{
+            return Err(Error::Frame("Message length less than 10"));
+        }
+        if message_length>self.max_length{
+            return Err(Error::MessageTooLong(message_length,self.max_length));
+        }
+        let frame_len = wire::LENGTH_PREFIX_LEN+message_length as usize;
+        if src.len()<frame_len{
+            src.reserve(frame_len-src.len());
+            return Ok(None);
+        }
+        let frame = src.split_to(frame_len);
+        let hsms_message_ref = HSMSMessageRef::from_bytes_with_quirks(&frame,&self.quirks)
+            .map_err(Error::Frame)?;
+        if self.mode==DecodeMode::Strict{
+            let violations = self.registries.validate(&hsms_message_ref);
+            if !violations.is_empty(){
+                return Err(Error::InvalidFrame(FrameError::Violations(violations)));
+            }
+        }
+        Ok(Some(hsms_message_ref.to_owned_message()))
+    }
+
+    //连接关闭时缓冲区里还有半帧，视为错误
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self.decode(src)? {
+            Some(hsms_message) => Ok(Some(hsms_message)),
+            None if !src.has_remaining() => Ok(None),
+            None => Err(Error::Frame("Connection closed with a partial frame")),
+        }
+    }
+}
+
+impl Encoder<HSMSMessage> for HsmsCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: HSMSMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode(&item,dst)
+    }
+}
+
+impl Encoder<&HSMSMessage> for HsmsCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: &HSMSMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode(item.as_message_ref(),dst)
+    }
+}
+
+impl Encoder<HSMSMessageRef<'_>> for HsmsCodec {
+    type Error = Error;
+
+    fn encode(&mut self, item: HSMSMessageRef<'_>, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        //带文本的控制消息等不合规的消息不写出
+        item.check()?;
+        if item.message_length()>self.max_length{
+            return Err(Error::MessageTooLong(item.message_length(),self.max_length));
+        }
+        item.encode_into(dst);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::hsms::{DeviceId, HSMSHeader, Role, SessionType};
+    use crate::secs2::EncodeError;
+    use crate::stream_function::S1F1;
+
+    fn linktest_req(system_bytes:u32)->HSMSMessage{
+        HSMSMessage::from_header(HSMSHeader::new(SessionType::LinktestReq,0xFFFF,0,0,0,0,0,0,system_bytes))
+    }
+
+    #[test]
+    fn test_decode_partial_and_multiple_frames(){
+        let mut codec = HsmsCodec::new();
+        let s1f1 = HSMSMessage::try_new(HSMSHeader::data_message(Role::Host,DeviceId::new(1).unwrap(),S1F1,true,2),vec![0x01,0x00]).unwrap();
+        let mut bytes = linktest_req(1).to_bytes();
+        bytes.extend_from_slice(&s1f1.to_bytes());
+
+        let mut src = BytesMut::new();
+        //逐字节送入，只有攒够一整帧才产出消息
+        let mut decoded = Vec::new();
+        for byte in &bytes{
+            src.extend_from_slice(&[*byte]);
+            if let Some(hsms_message) = codec.decode(&mut src).unwrap(){
+                decoded.extend_from_slice(&hsms_message.to_bytes());
+            }
+        }
+        assert_eq!(decoded,bytes);
+        assert!(src.is_empty());
+
+        let mut src = BytesMut::from(&bytes[..]);
+        assert_eq!(codec.decode(&mut src).unwrap().unwrap().hsms_header(),linktest_req(1).hsms_header());
+        assert_eq!(codec.decode(&mut src).unwrap(),Some(s1f1));
+        assert_eq!(codec.decode(&mut src).unwrap(),None);
+    }
+
+    #[test]
+    fn test_decode_errors(){
+        let mut codec = HsmsCodec::new().with_max_length(0x100);
+        let mut src = BytesMut::from(&[0x00,0x00,0x00,0x09][..]);
+        assert!(matches!(codec.decode(&mut src),Err(Error::Frame(_))));
+        let mut src = BytesMut::from(&[0x00,0x00,0x01,0x01][..]);
+        assert!(matches!(codec.decode(&mut src),Err(Error::MessageTooLong(0x101,0x100))));
+
+        let mut src = BytesMut::from(&linktest_req(1).to_bytes()[..13]);
+        assert_eq!(codec.decode_eof(&mut src).unwrap_err().to_string(),"Connection closed with a partial frame");
+        assert_eq!(codec.decode_eof(&mut BytesMut::new()).unwrap(),None);
+    }
+
+    #[test]
+    fn test_decode_little_endian_length(){
+        let mut codec = HsmsCodec::new().with_quirks(Quirks{little_endian_length:true});
+        let mut bytes = linktest_req(1).to_bytes();
+        bytes[0..4].copy_from_slice(&[0x0A,0x00,0x00,0x00]);
+        let mut src = BytesMut::from(&bytes[..]);
+        let hsms_message = codec.decode(&mut src).unwrap().unwrap();
+        assert_eq!(hsms_message.hsms_header(),linktest_req(1).hsms_header());
+        assert_eq!(hsms_message.encoded_len(),14);
+    }
+
+    #[test]
+    fn test_decode_strict_with_registries(){
+        use crate::extension::{PresentationCodec, SessionTypeExtension};
+
+        struct Vendor;
+
+        impl SessionTypeExtension for Vendor {
+            fn name(&self)->&str{
+                "Vendor"
+            }
+        }
+
+        impl PresentationCodec for Vendor {
+            fn name(&self)->&str{
+                "Vendor"
+            }
+
+            fn decode(&self,_message_text:&[u8])->Result<Box<dyn std::any::Any + Send>,&'static str>{
+                Ok(Box::new(()))
+            }
+        }
+
+        //SType 42、PType 0x80
+        let bytes:Vec<u8> = vec![0x00,0x00,0x00,0x0C,0xFF,0xFF,0x00,0x00,0x80,0x2A,0x00,0x00,0x00,0x01,0x01,0x02];
+        let mut codec = HsmsCodec::new().with_mode(DecodeMode::Strict);
+        let error = codec.decode(&mut BytesMut::from(&bytes[..])).unwrap_err();
+        assert_eq!(error.to_string(),"Reserved session type, Nonzero PType");
+
+        let mut registries = Registries::new();
+        registries.session_types.register(42,Box::new(Vendor)).unwrap();
+        registries.presentations.register(0x80,Box::new(Vendor)).unwrap();
+        let mut codec = codec.with_registries(Arc::new(registries));
+        let hsms_message = codec.decode(&mut BytesMut::from(&bytes[..])).unwrap().unwrap();
+        assert_eq!(hsms_message.to_bytes(),bytes);
+
+        //Lenient默认不校验
+        let mut codec = HsmsCodec::new();
+        assert!(codec.decode(&mut BytesMut::from(&bytes[..])).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_encode(){
+        let mut codec = HsmsCodec::new();
+        let mut dst = BytesMut::new();
+        codec.encode(linktest_req(1),&mut dst).unwrap();
+        codec.encode(&linktest_req(2),&mut dst).unwrap();
+        let mut expected = linktest_req(1).to_bytes();
+        expected.extend_from_slice(&linktest_req(2).to_bytes());
+        assert_eq!(&dst[..],&expected[..]);
+
+        let mut codec = HsmsCodec::new().with_max_length(10);
+        let s1f1 = HSMSMessage::try_new(HSMSHeader::data_message(Role::Host,DeviceId::new(1).unwrap(),S1F1,true,3),vec![0x00]).unwrap();
+        assert!(matches!(codec.encode(s1f1,&mut dst),Err(Error::MessageTooLong(11,10))));
+    }
+
+    #[test]
+    fn test_encode_control_message_with_text(){
+        let mut codec = HsmsCodec::new();
+        let mut dst = BytesMut::new();
+        let hsms_message = HSMSMessage::new_unchecked(linktest_req(1).hsms_header().clone(),vec![0x00]);
+        assert!(matches!(codec.encode(&hsms_message,&mut dst),Err(Error::Encode(EncodeError::ControlMessageWithText(5)))));
+        assert!(dst.is_empty());
+    }
+}
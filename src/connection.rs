@@ -0,0 +1,678 @@
+use std::time::Duration;
+use bytes::BytesMut;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio_util::codec::{Decoder, Encoder};
+use crate::codec::HsmsCodec;
+use crate::hsms::{DecodeMode, HSMSHeader, HSMSMessage, HSMSMessageRef, SessionType};
+use crate::utils::Error;
+
+/*
+ * @brief ConnectionState
+ * E37连接状态机
+ * NOT CONNECTED --TCP建立--> CONNECTED/NOT SELECTED --Select成功--> CONNECTED/SELECTED
+ * SELECTED --Deselect--> NOT SELECTED
+ * 任意状态 --Separate/TCP断开--> NOT CONNECTED
+ * 只有SELECTED状态可以收发数据消息
+ */
+#[derive(Debug,Clone,Copy,Eq, PartialEq)]
+pub enum ConnectionState{
+    NotConnected,
+    NotSelected,
+    Selected,
+}
+
+impl ConnectionState {
+    pub fn is_connected(&self)->bool{
+        *self!=ConnectionState::NotConnected
+    }
+}
+
+/*
+ * Select.rsp的状态码
+ * 0 成功
+ * 1 通信已建立
+ * 2 未就绪
+ * 3 连接已用尽
+ */
+#[derive(Debug,Clone,Copy,Eq, PartialEq,IntoPrimitive,TryFromPrimitive)]
+#[repr(u8)]
+pub enum SelectStatus{
+    Success = 0,
+    AlreadyActive = 1,
+    NotReady = 2,
+    Exhausted = 3,
+}
+
+/*
+ * Reject.req的原因码
+ * 1 不支持的SType
+ * 2 不支持的PType
+ * 3 没有对应的事务（未请求的回复）
+ * 4 未Select时收到数据消息
+ */
+#[derive(Debug,Clone,Copy,Eq, PartialEq,IntoPrimitive,TryFromPrimitive)]
+#[repr(u8)]
+pub enum RejectReason{
+    STypeNotSupported = 1,
+    PTypeNotSupported = 2,
+    TransactionNotOpen = 3,
+    EntityNotSelected = 4,
+}
+
+/*
+ * @brief HsmsConnection
+ * 持有连接并维护E37状态机，控制消息在receive内部自动应答：
+ *   Select.req    未Select时应答成功并进入SELECTED，否则应答AlreadyActive
+ *   Deselect.req  SELECTED时应答成功并回到NOT SELECTED，否则应答状态1
+ *   Linktest.req  任意状态应答Linktest.rsp
+ *   Separate.req  关闭连接，进入NOT CONNECTED
+ *   未Select时收到的数据消息回复Reject.req，不交给调用方
+ *   未注册的PType回复Reject.req(2)，未定义且未注册的SType回复Reject.req(1)；
+ *   已注册的子标准SType和非0 PType的消息按数据消息处理，交给调用方
+ * 注册表取自codec；codec为DecodeMode::Strict时违规项由连接自己检查并回复Reject.req，不断开连接，
+ * 只有长度非法、超长等分帧错误才关闭连接
+ * t6为控制事务（Select）等待应答的超时，默认5秒
+ * 自动应答先放进write_buf，每次调用开始时写出，receive/wait_selected可以放在tokio::select!中：
+ * 被取消时已经改变的状态和对应的应答不会丢失，下一次调用会先把没写完的应答写出
+ * T通常为TcpStream，测试时可以用tokio::io::duplex
+ */
+#[derive(Debug)]
+pub struct HsmsConnection<T=TcpStream>{
+    stream:Option<T>,
+    codec:HsmsCodec,
+    read_buf:BytesMut,
+    write_buf:BytesMut,
+    //codec本身总是按Lenient分帧，Strict的校验在dispatch中进行
+    mode:DecodeMode,
+    state:ConnectionState,
+    system_bytes:u32,
+    t6:Duration,
+}
+
+//E37常用的T6默认值
+pub const DEFAULT_T6:Duration = Duration::from_secs(5);
+
+impl HsmsConnection<TcpStream> {
+    //主动模式，连接成功后处于NOT SELECTED，需要再调用select
+    pub async fn connect(addr:impl ToSocketAddrs)->Result<HsmsConnection<TcpStream>,Error>{
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(true)?;
+        Ok(HsmsConnection::new(stream))
+    }
+}
+
+impl<T:AsyncRead+AsyncWrite+Unpin> HsmsConnection<T> {
+    //stream为已建立的连接，初始状态为NOT SELECTED
+    pub fn new(stream:T)->HsmsConnection<T>{
+        HsmsConnection::with_codec(stream,HsmsCodec::new())
+    }
+
+    pub fn with_codec(stream:T,codec:HsmsCodec)->HsmsConnection<T>{
+        HsmsConnection{
+            stream:Some(stream),
+            mode:codec.mode(),
+            codec:codec.with_mode(DecodeMode::Lenient),
+            read_buf:BytesMut::new(),
+            write_buf:BytesMut::new(),
+            state:ConnectionState::NotSelected,
+            system_bytes:0,
+            t6:DEFAULT_T6,
+        }
+    }
+
+    pub fn with_t6(mut self,t6:Duration)->HsmsConnection<T>{
+        self.t6 = t6;
+        self
+    }
+
+    pub fn state(&self)->ConnectionState{
+        self.state
+    }
+
+    //本端发起事务用的System Bytes，每次调用递增
+    pub fn next_system_bytes(&mut self)->u32{
+        self.system_bytes = self.system_bytes.wrapping_add(1);
+        self.system_bytes
+    }
+
+    /*
+     * 数据消息只能在SELECTED状态发送，控制消息不受限制
+     * 可以传&HSMSMessage，也可以传借用调用方缓冲区的HSMSMessageRef，后者发送时不复制消息文本
+     */
+    pub async fn send<'a>(&mut self,hsms_message:impl Into<HSMSMessageRef<'a>>)->Result<(),Error>{
+        let hsms_message = hsms_message.into();
+        if hsms_message.hsms_header().s_type()==u8::from(SessionType::SECS2) && self.state!=ConnectionState::Selected{
+            return Err(if self.state.is_connected() {Error::NotSelected} else {Error::NotConnected});
+        }
+        self.queue(hsms_message)?;
+        self.flush().await
+    }
+
+    /*
+     * 主动模式发起Select，等待对应的Select.rsp
+     * 等待期间收到的其他控制消息照常处理
+     * Select.rsp状态非0或对端用Reject.req拒绝时返回SelectRejected（状态码或原因码）
+     * T6内没有应答时关闭连接并返回Timeout
+     */
+    pub async fn select(&mut self)->Result<(),Error>{
+        match self.state {
+            ConnectionState::NotConnected => return Err(Error::NotConnected),
+            ConnectionState::Selected => return Ok(()),
+            ConnectionState::NotSelected => {}
+        }
+        let system_bytes = self.next_system_bytes();
+        let select_req = HSMSHeader::new(SessionType::SelectReq,0xFFFF,0,0,0,0,0,0,system_bytes);
+        self.write(&HSMSMessage::from_header(select_req)).await?;
+        match tokio::time::timeout(self.t6,self.wait_select_rsp(system_bytes)).await {
+            Ok(result) => result,
+            Err(_) => {
+                self.close().await;
+                Err(Error::Timeout("T6"))
+            }
+        }
+    }
+
+    async fn wait_select_rsp(&mut self,system_bytes:u32)->Result<(),Error>{
+        loop {
+            self.flush().await?;
+            let hsms_message = self.read().await?.ok_or(Error::NotConnected)?;
+            let hsms_header = hsms_message.hsms_header();
+            if hsms_header.system_bytes()==system_bytes{
+                match hsms_header.get_session_type() {
+                    Ok(SessionType::SelectRsp) => {
+                        if hsms_header.status()!=u8::from(SelectStatus::Success){
+                            return Err(Error::SelectRejected(hsms_header.status()));
+                        }
+                        self.state = ConnectionState::Selected;
+                        return Ok(());
+                    }
+                    //Reject.req的header byte 3为原因码
+                    Ok(SessionType::RejectReq) => return Err(Error::SelectRejected(hsms_header.status())),
+                    _ => {}
+                }
+            }
+            if let Some(data_message) = self.dispatch(hsms_message)?{
+                //对端在应答前就发了数据消息，不符合E37，直接拒绝
+                self.reject(&data_message,RejectReason::EntityNotSelected)?;
+            }
+        }
+    }
+
+    /*
+     * 被动模式等待对端的Select.req，应答后进入SELECTED
+     * 等待期间收到的数据消息按E37回复Reject.req
+     */
+    pub async fn wait_selected(&mut self)->Result<(),Error>{
+        loop {
+            //Select.rsp写出后才返回
+            self.flush().await?;
+            match self.state {
+                ConnectionState::Selected => return Ok(()),
+                ConnectionState::NotConnected => {
+                    self.close().await;
+                    return Err(Error::NotConnected);
+                }
+                ConnectionState::NotSelected => {}
+            }
+            let hsms_message = self.read().await?.ok_or(Error::NotConnected)?;
+            self.dispatch(hsms_message)?;
+        }
+    }
+
+    /*
+     * 接收下一条数据消息，控制消息在内部处理
+     * 连接关闭（对端断开或收到Separate.req）时返回None
+     */
+    pub async fn receive(&mut self)->Result<Option<HSMSMessage>,Error>{
+        loop {
+            self.flush().await?;
+            let Some(hsms_message) = self.read().await? else {
+                return Ok(None);
+            };
+            if let Some(data_message) = self.dispatch(hsms_message)?{
+                return Ok(Some(data_message));
+            }
+        }
+    }
+
+    //发送Separate.req并关闭连接
+    pub async fn separate(&mut self)->Result<(),Error>{
+        if !self.state.is_connected(){
+            return Ok(());
+        }
+        let system_bytes = self.next_system_bytes();
+        let separate_req = HSMSHeader::new(SessionType::SeparateReq,0xFFFF,0,0,0,0,0,0,system_bytes);
+        let result = self.write(&HSMSMessage::from_header(separate_req)).await;
+        self.close().await;
+        result
+    }
+
+    //先改状态、清缓冲区再shutdown，shutdown期间被取消时连接同样已经是NOT CONNECTED
+    pub async fn close(&mut self){
+        let stream = self.stream.take();
+        self.read_buf.clear();
+        self.write_buf.clear();
+        self.state = ConnectionState::NotConnected;
+        if let Some(mut stream) = stream{
+            //连接可能已经被对端关闭，忽略shutdown的错误
+            let _ = stream.shutdown().await;
+        }
+    }
+
+    /*
+     * 处理控制消息，应交给调用方的数据消息原样返回
+     * 不做I/O：状态在这里改变，应答只放进write_buf，Separate.req只把状态改为NOT CONNECTED，由read关闭连接
+     */
+    fn dispatch(&mut self,hsms_message:HSMSMessage)->Result<Option<HSMSMessage>,Error>{
+        if let Some(reason) = self.check(&hsms_message){
+            self.reject(&hsms_message,reason)?;
+            return Ok(None);
+        }
+        let hsms_header = hsms_message.hsms_header();
+        let session_id = hsms_header.session_id().value();
+        let system_bytes = hsms_header.system_bytes();
+        //check之后SType未定义说明是已注册的子标准SType
+        let session_type = hsms_header.get_session_type().unwrap_or(SessionType::SECS2);
+        match session_type {
+            SessionType::SECS2 => {
+                if self.state==ConnectionState::Selected{
+                    return Ok(Some(hsms_message));
+                }
+                self.reject(&hsms_message,RejectReason::EntityNotSelected)?;
+            }
+            SessionType::SelectReq => {
+                let status = if self.state==ConnectionState::Selected {
+                    SelectStatus::AlreadyActive
+                } else {
+                    self.state = ConnectionState::Selected;
+                    SelectStatus::Success
+                };
+                let select_rsp = HSMSHeader::new(SessionType::SelectRsp,session_id,0,0,0,0,0,status.into(),system_bytes);
+                self.queue(HSMSMessageRef::from_header(select_rsp))?;
+            }
+            SessionType::DeselectReq => {
+                //Deselect.rsp状态码 0 成功，1 通信未建立
+                let status = if self.state==ConnectionState::Selected {
+                    self.state = ConnectionState::NotSelected;
+                    0
+                } else {
+                    1
+                };
+                let deselect_rsp = HSMSHeader::new(SessionType::DeselectRsp,session_id,0,0,0,0,0,status,system_bytes);
+                self.queue(HSMSMessageRef::from_header(deselect_rsp))?;
+            }
+            SessionType::LinktestReq => {
+                let linktest_rsp = HSMSHeader::new(SessionType::LinktestRsp,0xFFFF,0,0,0,0,0,0,system_bytes);
+                self.queue(HSMSMessageRef::from_header(linktest_rsp))?;
+            }
+            SessionType::SelectRsp|SessionType::DeselectRsp|SessionType::LinktestRsp => {
+                //本端没有等待中的事务
+                self.reject(&hsms_message,RejectReason::TransactionNotOpen)?;
+            }
+            SessionType::RejectReq => {}
+            SessionType::SeparateReq => self.state = ConnectionState::NotConnected,
+        }
+        Ok(None)
+    }
+
+    /*
+     * 需要拒绝时返回Reject.req的原因码
+     * 先查codec的注册表，已注册的PType/SType不拒绝；
+     * Strict时其余违规项（控制消息带文本、扩展报告的违规等）按SType不支持拒绝
+     */
+    fn check(&self,hsms_message:&HSMSMessage)->Option<RejectReason>{
+        let hsms_header = hsms_message.hsms_header();
+        let registries = self.codec.registries();
+        if !registries.is_known_p_type(hsms_header.p_type()){
+            return Some(RejectReason::PTypeNotSupported);
+        }
+        if !registries.is_known_s_type(hsms_header.s_type()){
+            return Some(RejectReason::STypeNotSupported);
+        }
+        if self.mode==DecodeMode::Strict && !registries.validate(&hsms_message.as_message_ref()).is_empty(){
+            return Some(RejectReason::STypeNotSupported);
+        }
+        None
+    }
+
+    fn reject(&mut self,hsms_message:&HSMSMessage,reason:RejectReason)->Result<(),Error>{
+        let hsms_header = hsms_message.hsms_header();
+        //PType不支持时header byte 2填PType，其余填SType
+        let header_byte2 = if reason==RejectReason::PTypeNotSupported {
+            hsms_header.p_type()
+        } else {
+            hsms_header.s_type()
+        };
+        let reject_req = HSMSHeader::new(SessionType::RejectReq,hsms_header.session_id().value(),0,0,
+                                         header_byte2,0,0,reason.into(),hsms_header.system_bytes());
+        self.queue(HSMSMessageRef::from_header(reject_req))
+    }
+
+    async fn write(&mut self,hsms_message:&HSMSMessage)->Result<(),Error>{
+        self.queue(hsms_message.as_message_ref())?;
+        self.flush().await
+    }
+
+    //编码到write_buf，排在之前没写完的字节之后
+    fn queue(&mut self,hsms_message:HSMSMessageRef<'_>)->Result<(),Error>{
+        if self.stream.is_none(){
+            return Err(Error::NotConnected);
+        }
+        self.codec.encode(hsms_message,&mut self.write_buf)
+    }
+
+    //write_buf每次只推进实际写出的字节，被取消时剩余部分留到下次写出，不会出现半帧
+    async fn flush(&mut self)->Result<(),Error>{
+        while !self.write_buf.is_empty(){
+            let stream = self.stream.as_mut().ok_or(Error::NotConnected)?;
+            if stream.write_buf(&mut self.write_buf).await?==0{
+                return Err(std::io::Error::from(std::io::ErrorKind::WriteZero).into());
+            }
+        }
+        Ok(())
+    }
+
+    /*
+     * 读取下一帧，对端关闭连接时进入NOT CONNECTED并返回None
+     * 帧错误（长度非法、超长）后数据流已经不可信，同样关闭连接再返回错误
+     */
+    async fn read(&mut self)->Result<Option<HSMSMessage>,Error>{
+        loop {
+            if !self.state.is_connected(){
+                //收到Separate.req后还没有关闭
+                self.close().await;
+                return Ok(None);
+            }
+            match self.codec.decode(&mut self.read_buf) {
+                Ok(Some(hsms_message)) => return Ok(Some(hsms_message)),
+                Ok(None) => {}
+                Err(error) => {
+                    self.close().await;
+                    return Err(error);
+                }
+            }
+            let Some(stream) = self.stream.as_mut() else {
+                return Ok(None);
+            };
+            if stream.read_buf(&mut self.read_buf).await?==0{
+                let result = self.codec.decode_eof(&mut self.read_buf);
+                self.close().await;
+                return result;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use std::any::Any;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll};
+    use tokio::io::{DuplexStream, ReadBuf};
+    use crate::extension::{PresentationCodec, Registries, SessionTypeExtension};
+    use crate::hsms::{DeviceId, Role};
+    use crate::stream_function::S1F1;
+
+    fn s1f1(role:Role,system_bytes:u32)->HSMSMessage{
+        HSMSMessage::try_new(HSMSHeader::data_message(role,DeviceId::new(1).unwrap(),S1F1,true,system_bytes),vec![0x01,0x00]).unwrap()
+    }
+
+    fn pair()->(HsmsConnection<DuplexStream>,HsmsConnection<DuplexStream>){
+        let (host,equipment) = tokio::io::duplex(1024);
+        (HsmsConnection::new(host),HsmsConnection::new(equipment))
+    }
+
+    #[tokio::test]
+    async fn test_select_and_exchange(){
+        let (mut host,mut equipment) = pair();
+        assert_eq!(host.state(),ConnectionState::NotSelected);
+        assert!(matches!(host.send(&s1f1(Role::Host,1)).await,Err(Error::NotSelected)));
+
+        let (sent,received) = tokio::join!(
+            async {
+                host.select().await?;
+                host.send(&s1f1(Role::Host,2)).await
+            },
+            equipment.receive(),
+        );
+        sent.unwrap();
+        assert_eq!(received.unwrap(),Some(s1f1(Role::Host,2)));
+        assert_eq!(host.state(),ConnectionState::Selected);
+        assert_eq!(equipment.state(),ConnectionState::Selected);
+
+        //借用调用方缓冲区发送，不复制消息文本
+        let body = [0x01,0x00];
+        let s1f1_ref = HSMSMessageRef::try_new(s1f1(Role::Host,3).hsms_header().clone(),&body).unwrap();
+        let (sent,received) = tokio::join!(host.send(s1f1_ref),equipment.receive());
+        sent.unwrap();
+        assert_eq!(received.unwrap(),Some(s1f1(Role::Host,3)));
+
+        let (separated,received) = tokio::join!(host.separate(),equipment.receive());
+        separated.unwrap();
+        assert_eq!(received.unwrap(),None);
+        assert_eq!(host.state(),ConnectionState::NotConnected);
+        assert_eq!(equipment.state(),ConnectionState::NotConnected);
+        assert!(matches!(host.send(&s1f1(Role::Host,3)).await,Err(Error::NotConnected)));
+    }
+
+    #[tokio::test]
+    async fn test_reject_data_while_not_selected(){
+        let (host,equipment) = tokio::io::duplex(1024);
+        let mut equipment = HsmsConnection::new(equipment);
+        let mut host = HsmsConnection::new(host);
+
+        //绕过状态检查，直接写出未Select时的数据消息
+        host.write(&s1f1(Role::Host,7)).await.unwrap();
+        let linktest_req = HSMSHeader::new(SessionType::LinktestReq,0xFFFF,0,0,0,0,0,0,8);
+        host.write(&HSMSMessage::from_header(linktest_req)).await.unwrap();
+        let separate_req = HSMSHeader::new(SessionType::SeparateReq,0xFFFF,0,0,0,0,0,0,9);
+        host.write(&HSMSMessage::from_header(separate_req)).await.unwrap();
+
+        assert_eq!(equipment.receive().await.unwrap(),None);
+        assert_eq!(equipment.state(),ConnectionState::NotConnected);
+
+        let reject_req = host.read().await.unwrap().unwrap();
+        assert_eq!(reject_req.hsms_header().get_session_type(),Ok(SessionType::RejectReq));
+        assert_eq!(reject_req.hsms_header().status(),u8::from(RejectReason::EntityNotSelected));
+        assert_eq!(reject_req.hsms_header().system_bytes(),7);
+        let linktest_rsp = host.read().await.unwrap().unwrap();
+        assert_eq!(linktest_rsp.hsms_header().get_session_type(),Ok(SessionType::LinktestRsp));
+        assert_eq!(linktest_rsp.hsms_header().system_bytes(),8);
+        assert_eq!(host.read().await.unwrap(),None);
+        assert_eq!(host.state(),ConnectionState::NotConnected);
+    }
+
+    #[tokio::test]
+    async fn test_select_rejected(){
+        let (mut host,mut equipment) = pair();
+        let (selected,_) = tokio::join!(host.select(),async {
+            let select_req = equipment.read().await.unwrap().unwrap();
+            equipment.reject(&select_req,RejectReason::STypeNotSupported).unwrap();
+            equipment.flush().await.unwrap();
+        });
+        assert!(matches!(selected,Err(Error::SelectRejected(1))));
+        assert_eq!(host.state(),ConnectionState::NotSelected);
+
+        let (selected,_) = tokio::join!(host.select(),async {
+            let select_req = equipment.read().await.unwrap().unwrap();
+            let select_rsp = HSMSHeader::new(SessionType::SelectRsp,0xFFFF,0,0,0,0,0,SelectStatus::NotReady.into(),
+                                             select_req.hsms_header().system_bytes());
+            equipment.write(&HSMSMessage::from_header(select_rsp)).await.unwrap();
+        });
+        assert!(matches!(selected,Err(Error::SelectRejected(2))));
+    }
+
+    #[tokio::test]
+    async fn test_select_t6_timeout(){
+        let (host,_equipment) = tokio::io::duplex(1024);
+        let mut host = HsmsConnection::new(host).with_t6(Duration::from_millis(20));
+        assert!(matches!(host.select().await,Err(Error::Timeout("T6"))));
+        assert_eq!(host.state(),ConnectionState::NotConnected);
+    }
+
+    #[tokio::test]
+    async fn test_close_on_frame_error(){
+        let (mut host,mut equipment) = pair();
+        //长度字段为5，不足一个消息头
+        host.stream.as_mut().unwrap().write_all(&[0x00,0x00,0x00,0x05,0xFF,0xFF,0x00,0x00,0x00]).await.unwrap();
+        assert!(matches!(equipment.receive().await,Err(Error::Frame(_))));
+        assert_eq!(equipment.state(),ConnectionState::NotConnected);
+        assert_eq!(equipment.receive().await.unwrap(),None);
+        //对端看到连接被关闭
+        assert_eq!(host.receive().await.unwrap(),None);
+    }
+
+    #[tokio::test]
+    async fn test_strict_violation_rejected(){
+        let (host,equipment) = tokio::io::duplex(1024);
+        let mut host = HsmsConnection::new(host);
+        let mut equipment = HsmsConnection::with_codec(equipment,HsmsCodec::new().with_mode(DecodeMode::Strict));
+
+        //带文本的Select.req，Strict时回复Reject.req而不是断开
+        let select_req = HSMSHeader::new(SessionType::SelectReq,0xFFFF,0,0,0,0,0,0,7);
+        host.stream.as_mut().unwrap().write_all(&HSMSMessage::new_unchecked(select_req,vec![0x01]).to_bytes()).await.unwrap();
+        let (received,_) = tokio::join!(equipment.receive(),async {
+            let reject_req = host.read().await.unwrap().unwrap();
+            assert_eq!(reject_req.hsms_header().get_session_type(),Ok(SessionType::RejectReq));
+            assert_eq!(reject_req.hsms_header().status(),u8::from(RejectReason::STypeNotSupported));
+            assert_eq!(reject_req.hsms_header().system_bytes(),7);
+            //连接仍然可用，被拒绝的Select.req没有生效
+            host.select().await.unwrap();
+            host.separate().await.unwrap();
+        });
+        assert_eq!(received.unwrap(),None);
+    }
+
+    struct RawText;
+
+    impl PresentationCodec for RawText {
+        fn name(&self)->&str{
+            "RawText"
+        }
+
+        fn decode(&self,message_text:&[u8])->Result<Box<dyn Any + Send>,&'static str>{
+            Ok(Box::new(message_text.to_vec()))
+        }
+    }
+
+    struct VendorTrace;
+
+    impl SessionTypeExtension for VendorTrace {
+        fn name(&self)->&str{
+            "VendorTrace"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_registered_extensions(){
+        let mut registries = Registries::new();
+        registries.presentations.register(0x80,Box::new(RawText)).unwrap();
+        registries.session_types.register(42,Box::new(VendorTrace)).unwrap();
+        let codec = HsmsCodec::new().with_mode(DecodeMode::Strict).with_registries(Arc::new(registries));
+        let (host,equipment) = tokio::io::duplex(1024);
+        let mut host = HsmsConnection::new(host);
+        let mut equipment = HsmsConnection::with_codec(equipment,codec);
+        let (selected,waited) = tokio::join!(host.select(),equipment.wait_selected());
+        selected.unwrap();
+        waited.unwrap();
+
+        //PType 0x80和SType 42已注册，交给调用方
+        let registered_p_type:Vec<u8> = vec![0x00,0x00,0x00,0x0C,0x00,0x01,0x00,0x00,0x80,0x00,0x00,0x00,0x00,0x01,b'o',b'k'];
+        let registered_s_type:Vec<u8> = vec![0x00,0x00,0x00,0x0A,0xFF,0xFF,0x00,0x00,0x00,42,0x00,0x00,0x00,0x02];
+        //PType 0x81未注册
+        let unknown_p_type:Vec<u8> = vec![0x00,0x00,0x00,0x0A,0x00,0x01,0x00,0x00,0x81,0x00,0x00,0x00,0x00,0x03];
+        let stream = host.stream.as_mut().unwrap();
+        for frame in [&registered_p_type,&registered_s_type,&unknown_p_type]{
+            stream.write_all(frame).await.unwrap();
+        }
+        assert_eq!(equipment.receive().await.unwrap().unwrap().to_bytes(),registered_p_type);
+        assert_eq!(equipment.receive().await.unwrap().unwrap().to_bytes(),registered_s_type);
+
+        let (received,_) = tokio::join!(equipment.receive(),async {
+            let reject_req = host.read().await.unwrap().unwrap();
+            assert_eq!(reject_req.hsms_header().get_session_type(),Ok(SessionType::RejectReq));
+            assert_eq!(reject_req.hsms_header().status(),u8::from(RejectReason::PTypeNotSupported));
+            assert_eq!(reject_req.hsms_header().system_bytes(),3);
+            host.separate().await.unwrap();
+        });
+        assert_eq!(received.unwrap(),None);
+    }
+
+    //shutdown永远不完成的连接
+    struct PendingShutdown(DuplexStream);
+
+    impl AsyncRead for PendingShutdown {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.0).poll_read(cx,buf)
+        }
+    }
+
+    impl AsyncWrite for PendingShutdown {
+        fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+            Pin::new(&mut self.0).poll_write(cx,buf)
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Pin::new(&mut self.0).poll_flush(cx)
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+            Poll::Pending
+        }
+    }
+
+    #[tokio::test]
+    async fn test_close_cancelled(){
+        let (host,_equipment) = tokio::io::duplex(1024);
+        let mut host = HsmsConnection::new(PendingShutdown(host));
+        host.read_buf.extend_from_slice(&[0x00,0x00]);
+        host.write_buf.extend_from_slice(&[0x00,0x00]);
+        //close在shutdown处被取消
+        assert!(tokio::time::timeout(Duration::from_millis(20),host.close()).await.is_err());
+        assert_eq!(host.state(),ConnectionState::NotConnected);
+        assert!(host.read_buf.is_empty());
+        assert!(host.write_buf.is_empty());
+        let linktest_req = HSMSHeader::new(SessionType::LinktestReq,0xFFFF,0,0,0,0,0,0,1);
+        assert!(matches!(host.send(&HSMSMessage::from_header(linktest_req)).await,Err(Error::NotConnected)));
+        assert_eq!(host.receive().await.unwrap(),None);
+    }
+
+    #[tokio::test]
+    async fn test_select_twice_and_deselect(){
+        let (mut host,mut equipment) = pair();
+        let (selected,waited) = tokio::join!(host.select(),equipment.wait_selected());
+        selected.unwrap();
+        waited.unwrap();
+        assert_eq!(equipment.state(),ConnectionState::Selected);
+
+        //已经SELECTED时再收到Select.req应答AlreadyActive
+        let select_req = HSMSHeader::new(SessionType::SelectReq,0xFFFF,0,0,0,0,0,0,100);
+        host.write(&HSMSMessage::from_header(select_req)).await.unwrap();
+        let deselect_req = HSMSHeader::new(SessionType::DeselectReq,0xFFFF,0,0,0,0,0,0,101);
+        host.write(&HSMSMessage::from_header(deselect_req)).await.unwrap();
+
+        //逐帧处理但不写出应答，相当于receive在dispatch之后被取消
+        for _ in 0..2{
+            let hsms_message = equipment.read().await.unwrap().unwrap();
+            assert_eq!(equipment.dispatch(hsms_message).unwrap(),None);
+        }
+        assert_eq!(equipment.state(),ConnectionState::NotSelected);
+
+        //下一次receive先写出排队的应答
+        let (received,_) = tokio::join!(equipment.receive(),async {
+            let select_rsp = host.read().await.unwrap().unwrap();
+            assert_eq!(select_rsp.hsms_header().status(),u8::from(SelectStatus::AlreadyActive));
+            assert_eq!(select_rsp.hsms_header().system_bytes(),100);
+            let deselect_rsp = host.read().await.unwrap().unwrap();
+            assert_eq!(deselect_rsp.hsms_header().get_session_type(),Ok(SessionType::DeselectRsp));
+            assert_eq!(deselect_rsp.hsms_header().status(),0);
+            host.separate().await.unwrap();
+        });
+        assert_eq!(received.unwrap(),None);
+        assert_eq!(equipment.state(),ConnectionState::NotConnected);
+    }
+}
@@ -0,0 +1,317 @@
+use alloc::boxed::Box;
+use core::any::Any;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use crate::hsms::{HSMSHeader, HSMSMessageRef, SessionType, Violation};
+
+/*
+ * @brief SessionTypeExtension
+ * 子标准定义的SType（11-127）的扩展，由下游crate实现并注册
+ * 注册后该SType不再被视为预留值，校验时交给扩展处理
+ */
+pub trait SessionTypeExtension: Send + Sync {
+    fn name(&self)->&str;
+
+    //检查该SType的帧是否符合子标准，默认不检查
+    fn validate(&self,_message:&HSMSMessageRef<'_>)->Vec<Violation>{
+        Vec::new()
+    }
+}
+
+#[derive(Debug)]
+pub enum SessionTypeKind<'r>{
+    //E37定义的SType
+    Standard(SessionType),
+    //已注册的子标准SType
+    Subsidiary(u8,&'r dyn SessionTypeExtension),
+    //未定义、预留或未注册的SType
+    Unknown(u8),
+}
+
+impl core::fmt::Debug for dyn SessionTypeExtension + '_ {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug,Default)]
+pub struct SessionTypeRegistry{
+    extensions:BTreeMap<u8,Box<dyn SessionTypeExtension>>,
+}
+
+impl SessionTypeRegistry {
+    pub fn new()->SessionTypeRegistry{
+        SessionTypeRegistry::default()
+    }
+
+    //只允许注册11-127，同一SType不能重复注册
+    pub fn register(&mut self,s_type:u8,extension:Box<dyn SessionTypeExtension>)->Result<(),&'static str>{
+        if !(11..=127).contains(&s_type){
+            return Err("SType not in subsidiary range 11-127");
+        }
+        if self.extensions.contains_key(&s_type){
+            return Err("SType already registered");
+        }
+        self.extensions.insert(s_type,extension);
+        Ok(())
+    }
+
+    pub fn get(&self,s_type:u8)->Option<&dyn SessionTypeExtension>{
+        self.extensions.get(&s_type).map(|extension|extension.as_ref())
+    }
+
+    pub fn classify(&self,hsms_header:&HSMSHeader)->SessionTypeKind<'_>{
+        if let Ok(session_type) = hsms_header.get_session_type(){
+            return SessionTypeKind::Standard(session_type);
+        }
+        let s_type = hsms_header.s_type();
+        match self.get(s_type){
+            Some(extension) => SessionTypeKind::Subsidiary(s_type,extension),
+            None => SessionTypeKind::Unknown(s_type),
+        }
+    }
+
+    /*
+     * 与HSMSMessageRef::validate相同，但已注册的子标准SType不报ReservedSessionType，
+     * 改为调用扩展自身的校验
+     */
+    pub fn validate(&self,message:&HSMSMessageRef<'_>)->Vec<Violation>{
+        let mut violations = message.validate();
+        self.filter(message,&mut violations);
+        violations
+    }
+
+    //去掉已注册SType的ReservedSessionType，追加扩展自身的校验结果
+    fn filter(&self,message:&HSMSMessageRef<'_>,violations:&mut Vec<Violation>){
+        if let SessionTypeKind::Subsidiary(s_type,extension) = self.classify(message.hsms_header()){
+            violations.retain(|violation|*violation!=Violation::ReservedSessionType(s_type));
+            violations.extend(extension.validate(message));
+        }
+    }
+}
+
+/*
+ * @brief PresentationCodec
+ * 非0 PType的表示层编解码，消息文本不是SECS-II时（厂商XML或二进制格式）由其解析
+ * 解析结果的类型由实现决定，调用方通过downcast取得
+ */
+pub trait PresentationCodec: Send + Sync {
+    fn name(&self)->&str;
+
+    fn decode(&self,message_text:&[u8])->Result<Box<dyn Any + Send>,&'static str>;
+}
+
+impl core::fmt::Debug for dyn PresentationCodec + '_ {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug,Default)]
+pub struct PresentationRegistry{
+    codecs:BTreeMap<u8,Box<dyn PresentationCodec>>,
+}
+
+impl PresentationRegistry {
+    pub fn new()->PresentationRegistry{
+        PresentationRegistry::default()
+    }
+
+    //PType 0为SECS-II，不能被替换
+    pub fn register(&mut self,p_type:u8,codec:Box<dyn PresentationCodec>)->Result<(),&'static str>{
+        if p_type==0{
+            return Err("PType 0 is reserved for SECS-II");
+        }
+        if self.codecs.contains_key(&p_type){
+            return Err("PType already registered");
+        }
+        self.codecs.insert(p_type,codec);
+        Ok(())
+    }
+
+    pub fn get(&self,p_type:u8)->Option<&dyn PresentationCodec>{
+        self.codecs.get(&p_type).map(|codec|codec.as_ref())
+    }
+
+    //PType未注册（包括SECS-II）返回None，没有消息文本时按空文本解析
+    pub fn decode(&self,message:&HSMSMessageRef<'_>)->Option<Result<Box<dyn Any + Send>,&'static str>>{
+        let codec = self.get(message.hsms_header().p_type())?;
+        Some(codec.decode(message.message_text().unwrap_or_default()))
+    }
+
+    //已注册的PType不报NonZeroPType
+    pub fn validate(&self,message:&HSMSMessageRef<'_>)->Vec<Violation>{
+        let mut violations = message.validate();
+        self.filter(message,&mut violations);
+        violations
+    }
+
+    //去掉已注册PType的NonZeroPType
+    fn filter(&self,message:&HSMSMessageRef<'_>,violations:&mut Vec<Violation>){
+        let p_type = message.hsms_header().p_type();
+        if self.get(p_type).is_some(){
+            violations.retain(|violation|*violation!=Violation::NonZeroPType(p_type));
+        }
+    }
+}
+
+/*
+ * @brief Registries
+ * SType和PType两个注册表的组合，是校验的统一入口
+ * 两个注册表各自的validate只认识自己的扩展，同时用到子标准SType和非0 PType时用这里的validate
+ * 严格解码（from_bytes_with_registries）、帧同步（find_next_frame_with_registries）和HsmsCodec都用它
+ */
+#[derive(Debug,Default)]
+pub struct Registries{
+    pub session_types:SessionTypeRegistry,
+    pub presentations:PresentationRegistry,
+}
+
+impl Registries {
+    pub fn new()->Registries{
+        Registries::default()
+    }
+
+    //已注册的SType不报ReservedSessionType并调用扩展的校验，已注册的PType不报NonZeroPType
+    pub fn validate(&self,message:&HSMSMessageRef<'_>)->Vec<Violation>{
+        let mut violations = message.validate();
+        self.session_types.filter(message,&mut violations);
+        self.presentations.filter(message,&mut violations);
+        violations
+    }
+
+    //E37定义的SType或已注册的子标准SType
+    pub fn is_known_s_type(&self,s_type:u8)->bool{
+        SessionType::try_from(s_type).is_ok() || self.session_types.get(s_type).is_some()
+    }
+
+    //SECS-II或已注册的PType
+    pub fn is_known_p_type(&self,p_type:u8)->bool{
+        p_type==0 || self.presentations.get(p_type).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use alloc::vec;
+    use crate::hsms::{find_next_frame, find_next_frame_with_registries, is_plausible_frame_start,
+                      is_plausible_frame_start_with_registries, DecodeMode, FrameError, HSMSMessage};
+
+    struct VendorTrace;
+
+    impl SessionTypeExtension for VendorTrace {
+        fn name(&self)->&str{
+            "VendorTrace"
+        }
+
+        fn validate(&self,message:&HSMSMessageRef<'_>)->Vec<Violation>{
+            if message.message_text().is_none(){
+                return vec![Violation::Extension("VendorTrace without text")];
+            }
+            Vec::new()
+        }
+    }
+
+    struct Utf8Text;
+
+    impl PresentationCodec for Utf8Text {
+        fn name(&self)->&str{
+            "Utf8Text"
+        }
+
+        fn decode(&self,message_text:&[u8])->Result<Box<dyn Any + Send>,&'static str>{
+            let text = core::str::from_utf8(message_text).map_err(|_|"Invalid UTF-8")?;
+            Ok(Box::new(alloc::string::String::from(text)))
+        }
+    }
+
+    #[test]
+    fn test_presentation_registry(){
+        let mut registry = PresentationRegistry::new();
+        assert_eq!(registry.register(0,Box::new(Utf8Text)),Err("PType 0 is reserved for SECS-II"));
+        assert_eq!(registry.register(0x80,Box::new(Utf8Text)),Ok(()));
+        assert_eq!(registry.register(0x80,Box::new(Utf8Text)),Err("PType already registered"));
+
+        let xml:Vec<u8> = vec![0x00,0x00,0x00,0x0C,0x00,0x01,0x00,0x00,0x80,0x00,0x00,0x00,0x00,0x01,b'o',b'k'];
+        let message = HSMSMessageRef::from_bytes(&xml).unwrap();
+        let decoded = registry.decode(&message).unwrap().unwrap();
+        assert_eq!(decoded.downcast_ref::<alloc::string::String>().map(|text|text.as_str()),Some("ok"));
+        assert_eq!(message.validate(),vec![Violation::NonZeroPType(0x80)]);
+        assert!(registry.validate(&message).is_empty());
+
+        let secs2:Vec<u8> = vec![0x00,0x00,0x00,0x0A,0x00,0x01,0x81,0x01,0x00,0x00,0x00,0x00,0x00,0x01];
+        let message = HSMSMessageRef::from_bytes(&secs2).unwrap();
+        assert!(registry.decode(&message).is_none());
+    }
+
+    #[test]
+    fn test_register(){
+        let mut registry = SessionTypeRegistry::new();
+        assert_eq!(registry.register(10,Box::new(VendorTrace)),Err("SType not in subsidiary range 11-127"));
+        assert_eq!(registry.register(128,Box::new(VendorTrace)),Err("SType not in subsidiary range 11-127"));
+        assert_eq!(registry.register(42,Box::new(VendorTrace)),Ok(()));
+        assert_eq!(registry.register(42,Box::new(VendorTrace)),Err("SType already registered"));
+        assert_eq!(registry.get(42).map(|extension|extension.name()),Some("VendorTrace"));
+    }
+
+    #[test]
+    fn test_classify_and_validate(){
+        let mut registry = SessionTypeRegistry::new();
+        registry.register(42,Box::new(VendorTrace)).unwrap();
+
+        let select_req:Vec<u8> = vec![0x00,0x00,0x00,0x0A,0xFF,0xFF,0x00,0x00,0x00,0x01,0x00,0x00,0x00,0x01];
+        let message = HSMSMessageRef::from_bytes(&select_req).unwrap();
+        assert!(matches!(registry.classify(message.hsms_header()),SessionTypeKind::Standard(SessionType::SelectReq)));
+
+        let vendor:Vec<u8> = vec![0x00,0x00,0x00,0x0C,0xFF,0xFF,0x00,0x00,0x00,0x2A,0x00,0x00,0x00,0x01,0x01,0x02];
+        let message = HSMSMessageRef::from_bytes(&vendor).unwrap();
+        assert!(matches!(registry.classify(message.hsms_header()),SessionTypeKind::Subsidiary(42,_)));
+        assert_eq!(message.validate(),vec![Violation::ReservedSessionType(42)]);
+        assert!(registry.validate(&message).is_empty());
+
+        let unknown:Vec<u8> = vec![0x00,0x00,0x00,0x0A,0xFF,0xFF,0x00,0x00,0x00,0x2B,0x00,0x00,0x00,0x01];
+        let message = HSMSMessageRef::from_bytes(&unknown).unwrap();
+        assert!(matches!(registry.classify(message.hsms_header()),SessionTypeKind::Unknown(43)));
+        assert_eq!(registry.validate(&message),vec![Violation::ReservedSessionType(43)]);
+
+        let vendor_without_text:Vec<u8> = vec![0x00,0x00,0x00,0x0A,0xFF,0xFF,0x00,0x00,0x00,0x2A,0x00,0x00,0x00,0x01];
+        let message = HSMSMessageRef::from_bytes(&vendor_without_text).unwrap();
+        assert_eq!(registry.validate(&message),vec![Violation::Extension("VendorTrace without text")]);
+    }
+
+    #[test]
+    fn test_registries(){
+        let mut registries = Registries::new();
+        registries.session_types.register(42,Box::new(VendorTrace)).unwrap();
+        registries.presentations.register(0x80,Box::new(Utf8Text)).unwrap();
+
+        //SType 42、PType 0x80，两个注册表各自都只认识其中一项
+        let vendor:Vec<u8> = vec![0x00,0x00,0x00,0x0C,0xFF,0xFF,0x00,0x00,0x80,0x2A,0x00,0x00,0x00,0x01,b'o',b'k'];
+        let message = HSMSMessageRef::from_bytes(&vendor).unwrap();
+        assert_eq!(registries.session_types.validate(&message),vec![Violation::NonZeroPType(0x80)]);
+        assert_eq!(registries.presentations.validate(&message),vec![Violation::ReservedSessionType(42)]);
+        assert!(registries.validate(&message).is_empty());
+
+        let (message,violations) = HSMSMessageRef::from_bytes_with_registries(&vendor,DecodeMode::Strict,&registries).unwrap();
+        assert!(violations.is_empty());
+        assert_eq!(message.message_text(),Some(&b"ok"[..]));
+        assert!(HSMSMessageRef::from_bytes_with_mode(&vendor,DecodeMode::Strict).is_err());
+        let (_,violations) = HSMSMessage::from_bytes_with_registries(vendor.clone(),DecodeMode::Strict,&registries).unwrap();
+        assert!(violations.is_empty());
+
+        assert!(is_plausible_frame_start_with_registries(&vendor,0xFFFF,&registries));
+        assert!(!is_plausible_frame_start(&vendor,0xFFFF));
+        let mut stream:Vec<u8> = vec![0x12,0x34];
+        stream.extend_from_slice(&vendor);
+        stream.extend_from_slice(&vendor);
+        assert_eq!(find_next_frame_with_registries(&stream,0xFFFF,&registries),Some(2));
+        assert_ne!(find_next_frame(&stream,0xFFFF),Some(2));
+
+        //未注册的SType仍然报错
+        let mut unknown = vendor.clone();
+        unknown[9] = 0x2B;
+        assert_eq!(HSMSMessageRef::from_bytes_with_registries(&unknown,DecodeMode::Strict,&registries),
+                   Err(FrameError::Violations(vec![Violation::ReservedSessionType(43)])));
+    }
+}
@@ -1,6 +1,13 @@
 use num_enum::{IntoPrimitive, TryFromPrimitive, TryFromPrimitiveError};
 use serde::{Deserialize, Serialize};
-use crate::utils::serialize;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use bytes::{BufMut, BytesMut};
+use crate::extension::Registries;
+use crate::secs2::{DecodeError, EncodeError, Item};
+use crate::stream_function::{StreamFunction, MAX_STREAM};
+use crate::wire::{self, BigEndian};
 /**
  *@brief HSMSMessage
  *MessageLength
@@ -83,9 +90,11 @@ use crate::utils::serialize;
  * @brief MessageText
  * 消息文本 0-n bytes
  */
+//以上各段都是SessionType的文档注释，段间空行保留原有排版
+#[allow(clippy::empty_line_after_doc_comments)]
 #[derive(Debug,Eq, PartialEq,IntoPrimitive,TryFromPrimitive)]
 #[repr(u8)]
-enum SessionType{
+pub enum SessionType{
     SECS2 = 0,
     SelectReq =1,
     SelectRsp =2,
@@ -97,31 +106,149 @@ enum SessionType{
     SeparateReq = 9
 }
 #[derive(Debug,Clone,Eq, PartialEq,Serialize,Deserialize)]
-struct SessionID{
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SessionID{
     session_id:u16
 }
 
 impl SessionID {
-    fn from_direction_equip_id(direction:u16,equip_id:u16)->SessionID {
+    pub fn from_direction_equip_id(direction:u16,equip_id:u16)->SessionID {
         SessionID {
             session_id:(direction&0x8000) |(equip_id&0x7FFF)
         }
     }
+
+    pub fn new(direction:Direction,device_id:DeviceId)->SessionID{
+        SessionID::from_direction_equip_id(direction.bit(),device_id.value())
+    }
+
+    pub fn to_host(device_id:DeviceId)->SessionID{
+        SessionID::new(Direction::ToHost,device_id)
+    }
+
+    pub fn to_equipment(device_id:DeviceId)->SessionID{
+        SessionID::new(Direction::ToEquipment,device_id)
+    }
+
+    pub fn direction(&self)->Direction{
+        if self.session_id&0x8000!=0{
+            Direction::ToHost
+        }else{
+            Direction::ToEquipment
+        }
+    }
+
+    pub fn device_id(&self)->DeviceId{
+        DeviceId(self.session_id&0x7FFF)
+    }
+
+    pub fn value(&self)->u16{
+        self.session_id
+    }
+}
+
+/*
+ * @brief Direction
+ * SessionID最高位，1为To host，0为To equip
+ */
+#[derive(Debug,Clone,Copy,Eq, PartialEq,Hash)]
+pub enum Direction{
+    ToHost,
+    ToEquipment,
+}
+
+impl Direction {
+    fn bit(&self)->u16{
+        match self {
+            Direction::ToHost => 0x8000,
+            Direction::ToEquipment => 0,
+        }
+    }
+}
+
+/*
+ * @brief Role
+ * 本端角色，决定发出消息时SessionID的方向位
+ * Host发出的消息To equip，Equipment发出的消息To host
+ */
+#[derive(Debug,Clone,Copy,Eq, PartialEq,Hash)]
+pub enum Role{
+    Host,
+    Equipment,
+}
+
+impl Role {
+    pub fn peer(&self)->Role{
+        match self {
+            Role::Host => Role::Equipment,
+            Role::Equipment => Role::Host,
+        }
+    }
+
+    //本端发出消息的方向
+    pub fn outbound_direction(&self)->Direction{
+        match self {
+            Role::Host => Direction::ToEquipment,
+            Role::Equipment => Direction::ToHost,
+        }
+    }
+
+    pub fn session_id(&self,device_id:DeviceId)->SessionID{
+        SessionID::new(self.outbound_direction(),device_id)
+    }
+}
+
+/*
+ * @brief DeviceId
+ * SessionID低15位，唯一标识一台设备 0-32767
+ */
+#[derive(Debug,Clone,Copy,Eq, PartialEq,Hash,PartialOrd,Ord)]
+pub struct DeviceId(u16);
+
+impl DeviceId {
+    pub const MAX:DeviceId = DeviceId(0x7FFF);
+
+    pub fn new(device_id:u16)->Result<DeviceId,&'static str>{
+        if device_id>0x7FFF{
+            return Err("Device ID out of range");
+        }
+        Ok(DeviceId(device_id))
+    }
+
+    pub fn value(&self)->u16{
+        self.0
+    }
+}
+
+impl TryFrom<u16> for DeviceId {
+    type Error = &'static str;
+
+    fn try_from(device_id: u16) -> Result<Self, Self::Error> {
+        DeviceId::new(device_id)
+    }
+}
+
+impl From<DeviceId> for u16 {
+    fn from(device_id: DeviceId) -> Self {
+        device_id.0
+    }
 }
 #[derive(Debug,Clone,Eq, PartialEq,Serialize,Deserialize)]
-struct HeaderByte2{
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct HeaderByte2{
     header_byte2:u8,
 }
 
 impl HeaderByte2 {
-    fn from_w_bit_stream(w_bit:u8,stream:u8)->HeaderByte2{
+    pub fn from_w_bit_stream(w_bit:u8,stream:u8)->HeaderByte2{
         HeaderByte2{
             header_byte2: (w_bit&0x80)|(stream&0x7F)
         }
     }
 }
 #[derive(Debug,Clone,Eq, PartialEq,Serialize,Deserialize)]
-struct HSMSHeader {
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct HSMSHeader {
     session_id:SessionID,
     header_byte2:HeaderByte2,
     header_byte3:u8,
@@ -131,7 +258,8 @@ struct HSMSHeader {
 }
 
 impl HSMSHeader {
-    fn new(session_type:SessionType,
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(session_type:SessionType,
            session_id:u16,
            direction:u16,
            equip_id:u16,
@@ -146,10 +274,10 @@ impl HSMSHeader {
                 HSMSHeader{
                     session_id: SessionID::from_direction_equip_id(direction,equip_id),
                     header_byte2: HeaderByte2::from_w_bit_stream(w_bit,stream),
-                    header_byte3: header_byte3,
+                    header_byte3,
                     p_type: 0,
                     s_type: session_type.into(),
-                    system_bytes: system_bytes,
+                    system_bytes,
                 }
             }
             SessionType::SelectReq=>{
@@ -159,17 +287,17 @@ impl HSMSHeader {
                     header_byte3: 0,
                     p_type: 0,
                     s_type: session_type.into(),
-                    system_bytes: system_bytes,
+                    system_bytes,
                 }
             }
             SessionType::SelectRsp => {
                 HSMSHeader{
-                    session_id: SessionID {session_id:session_id},
+                    session_id: SessionID {session_id},
                     header_byte2: HeaderByte2{header_byte2:0},
-                    header_byte3: header_byte3,
+                    header_byte3,
                     p_type: 0,
                     s_type: session_type.into(),
-                    system_bytes: system_bytes,
+                    system_bytes,
                 }
             }
             SessionType::DeselectReq => {
@@ -179,17 +307,17 @@ impl HSMSHeader {
                     header_byte3: 0,
                     p_type: 0,
                     s_type: session_type.into(),
-                    system_bytes: system_bytes,
+                    system_bytes,
                 }
             }
             SessionType::DeselectRsp => {
                 HSMSHeader{
-                    session_id: SessionID {session_id:session_id},
+                    session_id: SessionID {session_id},
                     header_byte2: HeaderByte2{header_byte2:0},
-                    header_byte3: header_byte3,
+                    header_byte3,
                     p_type: 0,
                     s_type: session_type.into(),
-                    system_bytes: system_bytes,
+                    system_bytes,
                 }
             }
             SessionType::LinktestReq => {
@@ -199,7 +327,7 @@ impl HSMSHeader {
                     header_byte3: 0,
                     p_type: 0,
                     s_type: session_type.into(),
-                    system_bytes: system_bytes,
+                    system_bytes,
                 }
             }
             SessionType::LinktestRsp => {
@@ -209,17 +337,17 @@ impl HSMSHeader {
                     header_byte3: 0,
                     p_type: 0,
                     s_type: session_type.into(),
-                    system_bytes: system_bytes,
+                    system_bytes,
                 }
             }
             SessionType::RejectReq => {
                 HSMSHeader{
-                    session_id: SessionID {session_id:session_id},
-                    header_byte2: HeaderByte2 {header_byte2:header_byte2},
-                    header_byte3: header_byte3,
+                    session_id: SessionID {session_id},
+                    header_byte2: HeaderByte2 {header_byte2},
+                    header_byte3,
                     p_type: 0,
                     s_type: session_type.into(),
-                    system_bytes: system_bytes,
+                    system_bytes,
                 }
             }
             SessionType::SeparateReq => {
@@ -229,70 +357,680 @@ impl HSMSHeader {
                     header_byte3: 0,
                     p_type: 0,
                     s_type: session_type.into(),
-                    system_bytes: system_bytes,
+                    system_bytes,
                 }
             }
 
         }
     }
-    fn get_session_type(&self) -> Result<SessionType, TryFromPrimitiveError<SessionType>> {
-        Ok(SessionType::try_from(self.s_type)?)
+    //按本端角色构造数据消息头，方向位由role决定；Stream超过MAX_STREAM时debug构建下panic
+    pub fn data_message(role:Role,device_id:DeviceId,stream_function:StreamFunction,
+                        w_bit:bool,system_bytes:u32)->HSMSHeader{
+        //Stream只有7位，超过时最高位会与W-Bit冲突
+        debug_assert!(stream_function.0<=MAX_STREAM,"stream {} exceeds 7 bits",stream_function.0);
+        HSMSHeader{
+            session_id: role.session_id(device_id),
+            header_byte2: HeaderByte2::from_w_bit_stream(if w_bit {0x80} else {0},stream_function.0),
+            header_byte3: stream_function.1,
+            p_type: 0,
+            s_type: SessionType::SECS2.into(),
+            system_bytes,
+        }
+    }
+
+    pub fn s_type(&self)->u8{
+        self.s_type
+    }
+
+    pub fn p_type(&self)->u8{
+        self.p_type
+    }
+
+    pub fn session_id(&self)->&SessionID{
+        &self.session_id
+    }
+
+    pub fn system_bytes(&self)->u32{
+        self.system_bytes
+    }
+
+    //控制消息的状态码（Select.rsp/Deselect.rsp）或原因码（Reject.req），即header byte 3
+    pub fn status(&self)->u8{
+        self.header_byte3
+    }
+
+    pub fn get_session_type(&self) -> Result<SessionType, TryFromPrimitiveError<SessionType>> {
+        SessionType::try_from(self.s_type)
     }
     fn len(&self)->u32{
-        10
+        wire::HEADER_LEN as u32
+    }
+
+    /*
+     * 检查本消息作为primary的回复是否合理
+     * primary的W-Bit为0时不应收到回复，收到则返回ReplyToNoReplyPrimary
+     * 本消息不是secondary或Stream与primary不同时不算回复，返回None
+     */
+    pub fn validate_reply(&self,primary:&HSMSHeader)->Option<Violation>{
+        let stream_function = self.stream_function()?;
+        let primary_stream_function = primary.stream_function()?;
+        if stream_function.is_primary() || stream_function.0!=primary_stream_function.0{
+            return None;
+        }
+        if !primary.w_bit(){
+            return Some(Violation::ReplyToNoReplyPrimary(stream_function));
+        }
+        None
+    }
+
+    //仅SECS-II数据消息有SnFn，控制消息返回None
+    pub fn stream_function(&self)->Option<StreamFunction>{
+        if self.s_type!=0{
+            return None;
+        }
+        Some(StreamFunction(self.header_byte2.header_byte2&0x7F,self.header_byte3))
+    }
+
+    pub fn w_bit(&self)->bool{
+        self.s_type==0 && self.header_byte2.header_byte2&0x80!=0
+    }
+
+    /*
+     * 按E37网络字节序（大端）逐字段编码，不经过serde/bincode
+     * 直接在栈上生成10字节数组，不分配内存
+     */
+    pub fn to_array(&self)->[u8;wire::HEADER_LEN]{
+        let fields = [self.header_byte2.header_byte2,self.header_byte3,self.p_type,self.s_type];
+        wire::encode_header(self.session_id.session_id,fields,self.system_bytes)
+    }
+
+    pub fn from_array(bytes:&[u8;wire::HEADER_LEN])->HSMSHeader{
+        let (session_id,[header_byte2,header_byte3,p_type,s_type],system_bytes) = wire::decode_header(bytes);
+        HSMSHeader{
+            session_id: SessionID {session_id},
+            header_byte2: HeaderByte2 {header_byte2},
+            header_byte3,
+            p_type,
+            s_type,
+            system_bytes,
+        }
     }
 }
 
 #[derive(Debug,Clone,Eq, PartialEq)]
-struct HSMSMessage{
+pub struct HSMSMessage{
     message_length:u32,
     hsms_header:HSMSHeader,
     message_text:Option<Vec<u8>>
 }
 
 impl HSMSMessage {
-    fn new(hsms_header:HSMSHeader,message_text:&Vec<u8>)->HSMSMessage{
+    /*
+     * 检查后构造，控制消息带文本时返回ControlMessageWithText，总长度超过u32时返回TextTooLong
+     * 传入Vec时直接取得所有权，传入切片时复制一次；不想复制时用HSMSMessageRef::try_new借用
+     */
+    pub fn try_new(hsms_header:HSMSHeader,message_text:impl Into<Vec<u8>>)->Result<HSMSMessage,EncodeError>{
+        let message_text = message_text.into();
+        let message_length = check_message_text(&hsms_header,&message_text)?;
+        Ok(HSMSMessage{
+            message_length,
+            hsms_header,
+            message_text:Some(message_text)
+        })
+    }
+
+    //只有消息头的消息（控制消息、S1F1等），总是合法
+    pub fn from_header(hsms_header:HSMSHeader)->HSMSMessage{
+        HSMSMessage{
+            message_length:hsms_header.len(),
+            hsms_header,
+            message_text:None
+        }
+    }
+
+    /*
+     * 不做任何检查，用于诊断时按原样重放不合规的帧
+     * 这样构造的带文本控制消息会被HsmsCodec拒绝，只能用to_bytes/encode_into直接写出
+     * 消息头加消息文本超过u32::MAX字节时panic
+     */
+    pub fn new_unchecked(hsms_header:HSMSHeader,message_text:impl Into<Vec<u8>>)->HSMSMessage{
+        let message_text = message_text.into();
         HSMSMessage{
-            message_length:hsms_header.len()+message_text.len() as u32,
-            hsms_header: hsms_header,
-            message_text:Some(message_text.to_vec())
+            message_length:message_length(&hsms_header,message_text.len()).expect("message text exceeds the length field"),
+            hsms_header,
+            message_text:Some(message_text)
         }
     }
 
-    fn from_bytes(vec:Vec<u8>)->Result<HSMSMessage,&'static str>{
-        if vec.len()<14{
-            return Err("Size less than 14");
+    //把Item编码为消息文本，消息头不是数据消息时返回NotDataMessage
+    pub fn encode_body(hsms_header:HSMSHeader,item:&Item)->Result<HSMSMessage,EncodeError>{
+        if hsms_header.s_type!=0{
+            return Err(EncodeError::NotDataMessage(hsms_header.s_type));
         }
-        let message_length:u32 = bincode::deserialize(&vec[0..4]).unwrap();
-        let hsms_header:HSMSHeader = serialize::deserialize_from_bytes(&vec[4..14])
-            .expect("Deserialize hsms header fail");
+        HSMSMessage::try_new(hsms_header,item.encode()?)
+    }
+
+    //消息文本解析为Item，没有消息文本（如S1F1、控制消息）时返回None
+    pub fn decode_body(&self)->Result<Option<Item>,DecodeError>{
+        self.as_message_ref().decode_body()
+    }
+
+    pub fn hsms_header(&self)->&HSMSHeader{
+        &self.hsms_header
+    }
+
+    pub fn message_text(&self)->Option<&[u8]>{
+        self.message_text.as_deref()
+    }
+
+    pub fn from_bytes(vec:Vec<u8>)->Result<HSMSMessage,&'static str>{
+        let HSMSMessageRef{message_length,hsms_header,..} = HSMSMessageRef::from_bytes(&vec)?;
+        Ok(HSMSMessage::from_parts(vec,message_length,hsms_header))
+    }
+
+    //vec已经解析过长度和消息头
+    fn from_parts(mut vec:Vec<u8>,message_length:u32,hsms_header:HSMSHeader)->HSMSMessage{
         let mut message_text = None;
         if vec.len()>14{
-            message_text = Some(vec[14..].to_vec());
+            //原地移除长度和消息头，消息文本复用接收缓冲区
+            vec.drain(..14);
+            message_text = Some(vec);
+        }
+        HSMSMessage{
+            message_length,
+            hsms_header,
+            message_text,
+        }
+    }
+
+    //bytes必须已经是完整的一帧，长度前缀按quirks解释后写入message_length，分帧见HsmsCodec
+    pub fn from_bytes_with_quirks(vec:Vec<u8>,quirks:&Quirks)->Result<HSMSMessage,&'static str>{
+        let mut hsms_message = HSMSMessage::from_bytes(vec)?;
+        if quirks.little_endian_length{
+            hsms_message.message_length = hsms_message.message_length.swap_bytes();
         }
-        let hsms_message = HSMSMessage{
-            message_length: message_length,
-            hsms_header: hsms_header,
-            message_text: message_text,
-        };
         Ok(hsms_message)
     }
 
-    fn to_bytes(&self)->Vec<u8>{
-        let mut vec:Vec<u8>  = bincode::serialize(&self.message_length).unwrap();
-        vec.append(&mut serialize::serialize(&self.hsms_header));
-        if self.message_text.is_some(){
-            vec.append(&mut self.message_text.clone().unwrap());
+    pub fn from_bytes_with_mode(vec:Vec<u8>,mode:DecodeMode)
+        ->Result<(HSMSMessage,Vec<Violation>),FrameError>{
+        HSMSMessage::from_bytes_with_registries(vec,mode,&Registries::default())
+    }
+
+    //同from_bytes_with_mode，已注册的子标准SType和PType按registries校验
+    pub fn from_bytes_with_registries(vec:Vec<u8>,mode:DecodeMode,registries:&Registries)
+        ->Result<(HSMSMessage,Vec<Violation>),FrameError>{
+        let (HSMSMessageRef{message_length,hsms_header,..},violations) =
+            HSMSMessageRef::from_bytes_with_registries(&vec,mode,registries)?;
+        Ok((HSMSMessage::from_parts(vec,message_length,hsms_header),violations))
+    }
+
+    pub fn to_bytes(&self)->Vec<u8>{
+        let mut vec:Vec<u8> = Vec::with_capacity(self.encoded_len());
+        vec.extend_from_slice(&self.length_to_array());
+        vec.extend_from_slice(&self.hsms_header.to_array());
+        if let Some(message_text) = &self.message_text{
+            vec.extend_from_slice(message_text);
         }
         vec
     }
 
+    //编码后的总字节数，4字节长度加消息头和消息文本
+    pub fn encoded_len(&self)->usize{
+        self.as_message_ref().encoded_len()
+    }
+
+    //4字节长度前缀，大端
+    pub fn length_to_array(&self)->[u8;4]{
+        wire::encode_length(self.message_length)
+    }
+
+    pub fn encode_into(&self,buf:&mut BytesMut){
+        self.as_message_ref().encode_into(buf)
+    }
+
+    //写入调用方提供的缓冲区，返回写入的字节数
+    pub fn encode_into_slice(&self,buf:&mut [u8])->Result<usize,&'static str>{
+        self.as_message_ref().encode_into_slice(buf)
+    }
+
+    pub fn as_message_ref(&self)->HSMSMessageRef<'_>{
+        HSMSMessageRef::from(self)
+    }
+}
+
+impl<'a> From<&'a HSMSMessage> for HSMSMessageRef<'a> {
+    fn from(hsms_message: &'a HSMSMessage) -> Self {
+        HSMSMessageRef{
+            message_length:hsms_message.message_length,
+            hsms_header:hsms_message.hsms_header.clone(),
+            message_text:hsms_message.message_text.as_deref(),
+        }
+    }
+}
+
+/*
+ * @brief DecodeMode
+ * Strict  遇到任何违反E37的情况直接返回错误
+ * Lenient 照常解析，把违规项一并返回，由调用方决定如何处理
+ * 现场设备经常不完全遵守规范，两种行为都需要
+ */
+#[derive(Debug,Clone,Copy,Default,Eq, PartialEq)]
+pub enum DecodeMode{
+    Strict,
+    #[default]
+    Lenient,
+}
+
+/*
+ * @brief Quirks
+ * 针对不符合规范的设备的兼容开关，默认全部关闭
+ * 把兼容处理放在配置里，而不是为某个厂商修改代码
+ * 目前只有编解码层面的开关，会话层面的兼容项需要连接管理后再加
+ * little_endian_length只有HsmsCodec在分帧时按它读取长度前缀；
+ * from_bytes_with_quirks面对的是已经分好的帧，只修正message_length，
+ * is_plausible_frame_start/find_next_frame始终按大端读取，不处理任何Quirks
+ */
+#[derive(Debug,Clone,Default,Eq, PartialEq)]
+pub struct Quirks{
+    //长度字段按小端解析（已知部分厂商的实现有此问题）
+    pub little_endian_length:bool,
+}
+
+#[derive(Debug,Clone,Eq, PartialEq)]
+pub enum Violation{
+    //长度字段与实际收到的字节数不一致
+    LengthMismatch{declared:u32,actual:u32},
+    //SType为未定义或预留值
+    ReservedSessionType(u8),
+    //PType不为0，不是SECS-II消息
+    NonZeroPType(u8),
+    //偶数Function为回复消息，不能要求回复（W-Bit为1）
+    SecondaryWithWBit(StreamFunction),
+    //对方对不需要回复（W-Bit为0）的主消息发送了回复
+    ReplyToNoReplyPrimary(StreamFunction),
+    //控制消息（Select/Deselect/Linktest/Separate/Reject）不能带消息文本
+    ControlMessageWithText(u8),
+    //由扩展（子标准SType/PType）报告的违规
+    Extension(&'static str),
+}
+
+/*
+ * @brief FrameError
+ * from_bytes_with_mode的错误：帧本身无法解析，或Strict模式下存在违规项
+ * 违规项全部保留，调用方可以据此决定回复哪种Reject.req
+ */
+#[derive(Debug,Clone,Eq, PartialEq)]
+pub enum FrameError{
+    //不足14字节等无法解析的情况
+    Malformed(&'static str),
+    Violations(Vec<Violation>),
+}
+
+impl core::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            FrameError::Malformed(description) => f.write_str(description),
+            FrameError::Violations(violations) => {
+                for (i,violation) in violations.iter().enumerate(){
+                    if i>0{
+                        f.write_str(", ")?;
+                    }
+                    f.write_str(violation.description())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for FrameError {}
+
+impl Violation {
+    pub fn description(&self)->&'static str{
+        match self {
+            Violation::LengthMismatch{..} => "Message length mismatch",
+            Violation::ReservedSessionType(_) => "Reserved session type",
+            Violation::NonZeroPType(_) => "Nonzero PType",
+            Violation::SecondaryWithWBit(_) => "Secondary message with W-bit set",
+            Violation::ReplyToNoReplyPrimary(_) => "Reply to primary without W-bit",
+            Violation::ControlMessageWithText(_) => "Control message with text",
+            Violation::Extension(description) => description,
+        }
+    }
+}
+
+/*
+ * @brief HSMSMessageRef
+ * HSMSMessage的借用版本，消息文本直接引用接收缓冲区，解析时不分配内存
+ */
+#[derive(Debug,Clone,Eq, PartialEq)]
+pub struct HSMSMessageRef<'a>{
+    message_length:u32,
+    hsms_header:HSMSHeader,
+    message_text:Option<&'a [u8]>
+}
+
+impl<'a> HSMSMessageRef<'a> {
+    //引用调用方持有的消息文本，发送时不需要复制；检查同HSMSMessage::try_new
+    pub fn try_new(hsms_header:HSMSHeader,message_text:&'a [u8])->Result<HSMSMessageRef<'a>,EncodeError>{
+        let message_length = check_message_text(&hsms_header,message_text)?;
+        Ok(HSMSMessageRef{
+            message_length,
+            hsms_header,
+            message_text:Some(message_text)
+        })
+    }
+
+    pub fn from_header(hsms_header:HSMSHeader)->HSMSMessageRef<'a>{
+        HSMSMessageRef{
+            message_length:hsms_header.len(),
+            hsms_header,
+            message_text:None
+        }
+    }
+
+    //同HSMSMessage::new_unchecked，超过u32::MAX字节时panic
+    pub fn new_unchecked(hsms_header:HSMSHeader,message_text:&'a [u8])->HSMSMessageRef<'a>{
+        HSMSMessageRef{
+            message_length:message_length(&hsms_header,message_text.len()).expect("message text exceeds the length field"),
+            hsms_header,
+            message_text:Some(message_text)
+        }
+    }
+
+    //编码发送前的检查，HsmsCodec对每条发出的消息调用
+    pub fn check(&self)->Result<(),EncodeError>{
+        check_message_text(&self.hsms_header,self.message_text.unwrap_or_default()).map(|_|())
+    }
+
+    pub fn from_bytes(bytes:&'a [u8])->Result<HSMSMessageRef<'a>,&'static str>{
+        let (message_length,hsms_header) = split_length_header(bytes).ok_or("Size less than 14")?;
+        let mut message_text = None;
+        if bytes.len()>14{
+            message_text = Some(&bytes[14..]);
+        }
+        Ok(HSMSMessageRef{
+            message_length,
+            hsms_header,
+            message_text,
+        })
+    }
+
+    //同HSMSMessage::from_bytes_with_quirks，不负责分帧
+    pub fn from_bytes_with_quirks(bytes:&'a [u8],quirks:&Quirks)->Result<HSMSMessageRef<'a>,&'static str>{
+        let mut hsms_message_ref = HSMSMessageRef::from_bytes(bytes)?;
+        if quirks.little_endian_length{
+            hsms_message_ref.message_length = hsms_message_ref.message_length.swap_bytes();
+        }
+        Ok(hsms_message_ref)
+    }
+
+    pub fn from_bytes_with_mode(bytes:&'a [u8],mode:DecodeMode)
+        ->Result<(HSMSMessageRef<'a>,Vec<Violation>),FrameError>{
+        HSMSMessageRef::from_bytes_with_registries(bytes,mode,&Registries::default())
+    }
+
+    pub fn from_bytes_with_registries(bytes:&'a [u8],mode:DecodeMode,registries:&Registries)
+        ->Result<(HSMSMessageRef<'a>,Vec<Violation>),FrameError>{
+        let hsms_message_ref = HSMSMessageRef::from_bytes(bytes).map_err(FrameError::Malformed)?;
+        let violations = registries.validate(&hsms_message_ref);
+        if mode==DecodeMode::Strict && !violations.is_empty(){
+            return Err(FrameError::Violations(violations));
+        }
+        Ok((hsms_message_ref,violations))
+    }
+
+    //检查帧是否符合E37，返回所有违规项
+    pub fn validate(&self)->Vec<Violation>{
+        let mut violations = Vec::new();
+        let actual = message_length(&self.hsms_header,self.message_text.map_or(0,|text|text.len())).unwrap_or(u32::MAX);
+        if self.message_length!=actual{
+            violations.push(Violation::LengthMismatch{declared:self.message_length,actual});
+        }
+        match self.hsms_header.get_session_type(){
+            Err(_) => violations.push(Violation::ReservedSessionType(self.hsms_header.s_type)),
+            Ok(SessionType::SECS2) => {},
+            Ok(_) => {
+                if self.message_text.is_some_and(|text|!text.is_empty()){
+                    violations.push(Violation::ControlMessageWithText(self.hsms_header.s_type));
+                }
+            }
+        }
+        if self.hsms_header.p_type!=0{
+            violations.push(Violation::NonZeroPType(self.hsms_header.p_type));
+        }
+        if let Some(stream_function) = self.hsms_header.stream_function(){
+            if !stream_function.is_primary() && self.hsms_header.w_bit(){
+                violations.push(Violation::SecondaryWithWBit(stream_function));
+            }
+        }
+        violations
+    }
+
+    pub fn message_length(&self)->u32{
+        self.message_length
+    }
+
+    pub fn decode_body(&self)->Result<Option<Item>,DecodeError>{
+        if self.hsms_header.s_type!=0{
+            return Err(DecodeError::NotDataMessage(self.hsms_header.s_type));
+        }
+        if self.hsms_header.p_type!=0{
+            return Err(DecodeError::UnsupportedPType(self.hsms_header.p_type));
+        }
+        match self.message_text {
+            Some(message_text) if !message_text.is_empty() => Item::decode(message_text).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn hsms_header(&self)->&HSMSHeader{
+        &self.hsms_header
+    }
+
+    pub fn message_text(&self)->Option<&'a [u8]>{
+        self.message_text
+    }
+
+    pub fn encoded_len(&self)->usize{
+        4+self.hsms_header.len() as usize+self.message_text.map_or(0,|text|text.len())
+    }
+
+    pub fn encode_into(&self,buf:&mut BytesMut){
+        buf.reserve(self.encoded_len());
+        buf.put_slice(&wire::encode_length(self.message_length));
+        buf.put_slice(&self.hsms_header.to_array());
+        if let Some(message_text) = self.message_text{
+            buf.extend_from_slice(message_text);
+        }
+    }
+
+    pub fn encode_into_slice(&self,buf:&mut [u8])->Result<usize,&'static str>{
+        let encoded_len = self.encoded_len();
+        if buf.len()<encoded_len{
+            return Err("Buffer too small");
+        }
+        buf[0..4].copy_from_slice(&wire::encode_length(self.message_length));
+        buf[4..14].copy_from_slice(&self.hsms_header.to_array());
+        if let Some(message_text) = self.message_text{
+            buf[14..encoded_len].copy_from_slice(message_text);
+        }
+        Ok(encoded_len)
+    }
+
+    pub fn to_owned_message(&self)->HSMSMessage{
+        HSMSMessage{
+            message_length:self.message_length,
+            hsms_header:self.hsms_header.clone(),
+            message_text:self.message_text.map(|text|text.to_vec()),
+        }
+    }
+}
+
+/*
+ * 随机生成的消息总是满足 message_length = 10 + 文本长度，
+ * 空文本用None表示，与from_bytes的解析结果一致
+ */
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for HSMSMessage {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        let hsms_header:HSMSHeader = u.arbitrary()?;
+        let message_text:Vec<u8> = u.arbitrary()?;
+        let mut hsms_message = HSMSMessage::new_unchecked(hsms_header,&message_text[..]);
+        if message_text.is_empty(){
+            hsms_message.message_text = None;
+        }
+        Ok(hsms_message)
+    }
+}
+
+//消息头加消息文本的长度，超过4字节长度字段时返回None
+fn message_length(hsms_header:&HSMSHeader,text_length:usize)->Option<u32>{
+    u32::try_from(text_length).ok()?.checked_add(hsms_header.len())
+}
+
+//控制消息（Select/Deselect/Linktest/Separate/Reject）不能带消息文本，返回消息长度
+fn check_message_text(hsms_header:&HSMSHeader,message_text:&[u8])->Result<u32,EncodeError>{
+    if hsms_header.s_type!=0 && !message_text.is_empty(){
+        return Err(EncodeError::ControlMessageWithText(hsms_header.s_type));
+    }
+    message_length(hsms_header,message_text.len()).ok_or(EncodeError::TextTooLong(message_text.len()))
+}
+
+//读取4字节长度和10字节消息头，不足14字节返回None
+fn split_length_header(bytes:&[u8])->Option<(u32,HSMSHeader)>{
+    let (length,rest) = bytes.split_first_chunk::<{wire::LENGTH_PREFIX_LEN}>()?;
+    let (header,_) = rest.split_first_chunk::<{wire::HEADER_LEN}>()?;
+    Some((wire::decode_length(length),HSMSHeader::from_array(header)))
+}
+
+/*
+ * @brief 帧同步检查
+ * 长度字段与后续内容不符时数据流可能已经错位，继续按长度解析只会得到垃圾数据
+ * 合理的帧头：长度在10到max_length之间，PType为0，SType已定义，控制消息长度必须为10
+ * 长度前缀固定按大端读取，不支持Quirks::little_endian_length
+ */
+pub fn is_plausible_frame_start(bytes:&[u8],max_length:u32)->bool{
+    is_plausible_frame_start_with_registries(bytes,max_length,&Registries::default())
+}
+
+//已注册的PType和子标准SType也视为合理，子标准SType不限制长度
+pub fn is_plausible_frame_start_with_registries(bytes:&[u8],max_length:u32,registries:&Registries)->bool{
+    bytes.len()>=14 && is_plausible_prefix(bytes,max_length,registries)
+}
+
+//只检查bytes中已有的字段；长度前缀不完整时，缺失的字节按0计算可能的最小长度
+fn is_plausible_prefix(bytes:&[u8],max_length:u32,registries:&Registries)->bool{
+    let mut length = [0u8;wire::LENGTH_PREFIX_LEN];
+    let available = bytes.len().min(wire::LENGTH_PREFIX_LEN);
+    length[..available].copy_from_slice(&bytes[..available]);
+    let message_length = wire::decode_length(&length);
+    if message_length>max_length || (available==wire::LENGTH_PREFIX_LEN && message_length<10){
+        return false;
+    }
+    //消息头第5字节为PType，第6字节为SType
+    if bytes.get(8).is_some_and(|p_type|!registries.is_known_p_type(*p_type)){
+        return false;
+    }
+    match bytes.get(9).map(|s_type|(*s_type,SessionType::try_from(*s_type))) {
+        None|Some((_,Ok(SessionType::SECS2))) => true,
+        Some((_,Ok(_))) => message_length==10,
+        Some((s_type,Err(_))) => registries.is_known_s_type(s_type),
+    }
+}
+
+/*
+ * 从bytes中查找下一个合理的帧起始位置，用于错位后的重新同步
+ * 如果候选帧之后的数据也在缓冲区中，要求其后也是合理的帧头，减少误判
+ * 缓冲区末尾不足14字节、但已有字段都合理的候选也返回其位置，表示需要更多数据后再判断
+ * 找不到返回None，调用方可以选择断开连接
+ */
+pub fn find_next_frame(bytes:&[u8],max_length:u32)->Option<usize>{
+    find_next_frame_with_registries(bytes,max_length,&Registries::default())
+}
+
+pub fn find_next_frame_with_registries(bytes:&[u8],max_length:u32,registries:&Registries)->Option<usize>{
+    (0..bytes.len()).find(|&offset|{
+        let candidate = &bytes[offset..];
+        if !is_plausible_prefix(candidate,max_length,registries){
+            return false;
+        }
+        //长度前缀不完整，需要更多数据
+        let Some(message_length) = u32::read_be(candidate) else {
+            return true;
+        };
+        let Some(next) = wire::LENGTH_PREFIX_LEN.checked_add(message_length as usize) else {
+            return false;
+        };
+        match candidate.get(next..){
+            Some(rest) => is_plausible_prefix(rest,max_length,registries),
+            None => true,
+        }
+    })
+}
+
+/*
+ * @brief hexdump
+ * 按字段注释输出一帧的十六进制内容，用于和设备厂商工具排查线上字节问题
+ * 不要求帧合法，长度不足时只注释已有的字段
+ */
+pub fn hexdump(frame:&[u8])->String{
+    use core::fmt::Write;
+
+    fn row(out:&mut String,offset:usize,bytes:&[u8],annotation:&str){
+        let hex:Vec<String> = bytes.iter().map(|b|format!("{:02x}",b)).collect();
+        let _ = writeln!(out,"{:08x}  {:<47}  {}",offset,hex.join(" "),annotation);
+    }
+
+    let mut out = String::new();
+    let Some((message_length,hsms_header)) = split_length_header(frame) else {
+        for (i,chunk) in frame.chunks(16).enumerate(){
+            row(&mut out,i*16,chunk,"Truncated frame");
+        }
+        return out;
+    };
+    let session_id = hsms_header.session_id.session_id;
+    let header_byte2 = hsms_header.header_byte2.header_byte2;
+    let session_type = match hsms_header.get_session_type(){
+        Ok(session_type) => format!("{:?}",session_type),
+        Err(_) => "Unknown".to_string(),
+    };
+
+    row(&mut out,0,&frame[0..4],&format!("Message Length  {}",message_length));
+    row(&mut out,4,&frame[4..6],&format!("Session ID      0x{:04X} (direction {}, device {})",
+                                          session_id,session_id>>15,session_id&0x7FFF));
+    if hsms_header.s_type==0{
+        row(&mut out,6,&frame[6..7],&format!("Header Byte 2   0x{:02X} (W-bit {}, S{})",
+                                              header_byte2,header_byte2>>7,header_byte2&0x7F));
+        row(&mut out,7,&frame[7..8],&format!("Header Byte 3   0x{:02X} (F{})",
+                                              hsms_header.header_byte3,hsms_header.header_byte3));
+    }else{
+        row(&mut out,6,&frame[6..7],&format!("Header Byte 2   0x{:02X}",header_byte2));
+        row(&mut out,7,&frame[7..8],&format!("Header Byte 3   0x{:02X}",hsms_header.header_byte3));
+    }
+    row(&mut out,8,&frame[8..9],&format!("PType           {}",hsms_header.p_type));
+    row(&mut out,9,&frame[9..10],&format!("SType           {} ({})",hsms_header.s_type,session_type));
+    row(&mut out,10,&frame[10..14],&format!("System Bytes    0x{:08X}",hsms_header.system_bytes));
+    for (i,chunk) in frame[14..].chunks(16).enumerate(){
+        if i==0{
+            row(&mut out,14,chunk,&format!("Message Text    ({} bytes)",frame.len()-14));
+        }else{
+            row(&mut out,14+i*16,chunk,"");
+        }
+    }
+    out
 }
 
 
 #[cfg(test)]
 mod tests{
     use super::*;
+    use alloc::vec;
+    use crate::stream_function::{S1F1, S1F13, S1F2};
+    #[cfg(feature = "std")]
+    use crate::utils::serialize;
 
     #[test]
     fn test_session_id_from_direction_stream(){
@@ -301,6 +1039,35 @@ mod tests{
         assert_eq!(session_id,session_id_from_direction_stream);
     }
     #[test]
+    fn test_device_id(){
+        assert_eq!(DeviceId::new(0x7FFF),Ok(DeviceId::MAX));
+        assert_eq!(DeviceId::new(0x8000),Err("Device ID out of range"));
+        assert_eq!(DeviceId::try_from(1).map(u16::from),Ok(1));
+    }
+    #[test]
+    fn test_session_id_direction_device_id(){
+        let device_id = DeviceId::new(1).unwrap();
+        let session_id = SessionID::to_host(device_id);
+        assert_eq!(session_id,SessionID{session_id:0x8001});
+        assert_eq!(session_id.direction(),Direction::ToHost);
+        assert_eq!(session_id.device_id(),device_id);
+
+        let session_id = SessionID::to_equipment(device_id);
+        assert_eq!(session_id.value(),0x0001);
+        assert_eq!(session_id.direction(),Direction::ToEquipment);
+        assert_eq!(SessionID::new(Direction::ToEquipment,device_id),session_id);
+    }
+    #[test]
+    fn test_role(){
+        let device_id = DeviceId::new(1).unwrap();
+        assert_eq!(Role::Host.peer(),Role::Equipment);
+        assert_eq!(Role::Host.session_id(device_id).value(),0x0001);
+        assert_eq!(Role::Equipment.session_id(device_id).value(),0x8001);
+
+        let s1f1 = HSMSHeader::data_message(Role::Equipment,device_id,S1F1,true,0x11111111);
+        assert_eq!(s1f1,HSMSHeader::new(SessionType::SECS2,0,0x8000,1,0,0x80,1,1,0x11111111));
+    }
+    #[test]
     fn test_header_byte2_from_w_bit_stream(){
         let header_byte2 = HeaderByte2{header_byte2:0x81};
         let header_byte2_from_w_bit_stream = HeaderByte2::from_w_bit_stream(0x80,0x01);
@@ -351,6 +1118,16 @@ mod tests{
     }
     //todo!
 
+    #[test]
+    fn test_stream_function(){
+        let s1f1 = HSMSHeader::new(SessionType::SECS2,0,0,1,0,0x80,1,1,1);
+        assert_eq!(s1f1.stream_function(),Some(S1F1));
+        assert!(s1f1.w_bit());
+        let select_req = HSMSHeader::new(SessionType::SelectReq,0,0,0,0,0,0,0,1);
+        assert_eq!(select_req.stream_function(),None);
+        assert!(!select_req.w_bit());
+    }
+
     #[test]
     fn test_get_session_type(){
         let hsms_header_secs2 = HSMSHeader{
@@ -376,16 +1153,18 @@ mod tests{
     }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_serialize_session_id(){
         let session_id =SessionID{session_id:0x8FFF};
-        let session_id_bytes =  serialize::serialize(&session_id);
-        assert_eq!(session_id_bytes,vec![0xFF,0x8F]);
+        let session_id_bytes =  serialize::serialize(&session_id).unwrap();
+        assert_eq!(session_id_bytes,vec![0x8F,0xFF]);
     }
     #[test]
+    #[cfg(feature = "std")]
     fn test_deserialize_session_id_from_bytes(){
         let session_id =SessionID{session_id:0x8FFF};
-        let mut session_vec:Vec<u8> = vec![0xFF,0x8F];
-        let session_id_bytes:SessionID =  serialize::deserialize_from_bytes(&mut session_vec).unwrap();
+        let session_vec:Vec<u8> = vec![0x8F,0xFF];
+        let session_id_bytes:SessionID =  serialize::deserialize_from_bytes(&session_vec).unwrap();
         assert_eq!(session_id_bytes,session_id);
     }
 
@@ -401,19 +1180,22 @@ mod tests{
     // }
 
     #[test]
+    #[cfg(feature = "std")]
     fn test_serialize_header_byte2(){
         let header_byte2 = HeaderByte2{header_byte2:0x81};
-        let header_byte2_bytes =  serialize::serialize(&header_byte2);
+        let header_byte2_bytes =  serialize::serialize(&header_byte2).unwrap();
         assert_eq!(header_byte2_bytes,vec![0x81]);
     }
     #[test]
+    #[cfg(feature = "std")]
     fn test_deserialize_header_byte2_from_bytes(){
         let header_byte2 = HeaderByte2{header_byte2:0x81};
-        let mut header_byte2_vec:Vec<u8> = vec![0x81];
-        let header_byte2_bytes:HeaderByte2 =  serialize::deserialize_from_bytes(& mut header_byte2_vec).unwrap();
+        let header_byte2_vec:Vec<u8> = vec![0x81];
+        let header_byte2_bytes:HeaderByte2 =  serialize::deserialize_from_bytes(&header_byte2_vec).unwrap();
         assert_eq!(header_byte2_bytes,header_byte2);
     }
     #[test]
+    #[cfg(feature = "std")]
     fn test_serialize_hsms_header(){
         let hsms_header = HSMSHeader{
             session_id: SessionID {session_id:0xFFFF},
@@ -423,12 +1205,13 @@ mod tests{
             s_type: 1,
             system_bytes: 0x11111111,
         };
-        let hsms_header_bytes = serialize::serialize(&hsms_header);
+        let hsms_header_bytes = serialize::serialize(&hsms_header).unwrap();
         assert_eq!(hsms_header_bytes,vec![0xFF,0xFF,0x00,0x00,0x00,0x01,0x11,0x11,0x11,0x011]);
     }
     #[test]
+    #[cfg(feature = "std")]
     fn test_deserialize_hsms_header(){
-        let hsms_header_from_bytes:HSMSHeader = serialize::deserialize_from_bytes(&vec![0xFF,0xFF,0x00,0x00,0x00,0x01,0x11,0x11,0x11,0x011]).unwrap();
+        let hsms_header_from_bytes:HSMSHeader = serialize::deserialize_from_bytes(&[0xFF,0xFF,0x00,0x00,0x00,0x01,0x11,0x11,0x11,0x011]).unwrap();
         let hsms_header = HSMSHeader{
             session_id: SessionID {session_id:0xFFFF},
             header_byte2: HeaderByte2 {header_byte2:0},
@@ -441,6 +1224,25 @@ mod tests{
     }
 
 
+    #[test]
+    fn test_hsms_header_wire_big_endian(){
+        let hsms_header = HSMSHeader{
+            session_id: SessionID {session_id:0x8001},
+            header_byte2: HeaderByte2 {header_byte2:0x81},
+            header_byte3: 13,
+            p_type: 0,
+            s_type: 0,
+            system_bytes: 0x12345678,
+        };
+        let header_bytes = hsms_header.to_array();
+        assert_eq!(header_bytes,[0x80,0x01,0x81,0x0D,0x00,0x00,0x12,0x34,0x56,0x78]);
+        assert_eq!(HSMSHeader::from_array(&header_bytes),hsms_header);
+
+        let hsms_message = HSMSMessage::try_new(hsms_header,vec![0x01;0x100]).unwrap();
+        assert_eq!(hsms_message.length_to_array(),[0x00,0x00,0x01,0x0A]);
+        assert_eq!(&hsms_message.to_bytes()[0..4],&[0x00,0x00,0x01,0x0A]);
+    }
+
     #[test]
     fn test_hsms_message_new(){
         let hsms_header = HSMSHeader{
@@ -457,8 +1259,10 @@ mod tests{
             message_text:Some(vec![])
         };
 
-        let hsms_message_new = HSMSMessage::new(hsms_header,&vec![]);
+        let hsms_message_new = HSMSMessage::new_unchecked(hsms_header.clone(),Vec::new());
         assert_eq!(hsms_message,hsms_message_new);
+        assert_eq!(HSMSMessage::try_new(hsms_header.clone(),Vec::new()),Ok(hsms_message));
+        assert_eq!(HSMSMessage::from_header(hsms_header.clone()).to_bytes(),HSMSMessage::new_unchecked(hsms_header,Vec::new()).to_bytes());
 
         let hsms_header_with_text = HSMSHeader{
             session_id: SessionID {session_id:0x8001},
@@ -474,7 +1278,7 @@ mod tests{
             message_text:Some(vec![0x01,0x02])
         };
 
-        let hsms_message_new_with_text = HSMSMessage::new(hsms_header_with_text,&vec![0x01,0x02]);
+        let hsms_message_new_with_text = HSMSMessage::try_new(hsms_header_with_text,vec![0x01,0x02]).unwrap();
         assert_eq!(hsms_message_with_text,hsms_message_new_with_text);
     }
 
@@ -495,7 +1299,7 @@ mod tests{
         };
 
         let hsms_message_bytes = hsms_message.to_bytes();
-        assert_eq!(hsms_message_bytes,vec![0x0A,0x00,0x00,0x00,0xFF,0xFF,0x00,0x00,0x00,0x01,0x11,0x11,0x11,0x011])
+        assert_eq!(hsms_message_bytes,vec![0x00,0x00,0x00,0x0A,0xFF,0xFF,0x00,0x00,0x00,0x01,0x11,0x11,0x11,0x011])
     }
     #[test]
     fn test_hsms_message_to_bytes_with_message(){
@@ -514,12 +1318,52 @@ mod tests{
         };
 
         let hsms_message_bytes = hsms_message.to_bytes();
-        assert_eq!(hsms_message_bytes,vec![0x0C,0x00,0x00,0x00,0xFF,0xFF,0x00,0x00,0x00,0x00,0x11,0x11,0x11,0x011,0x01,0x02])
+        assert_eq!(hsms_message_bytes,vec![0x00,0x00,0x00,0x0C,0xFF,0xFF,0x00,0x00,0x00,0x00,0x11,0x11,0x11,0x011,0x01,0x02])
+    }
+    #[test]
+    fn test_hsms_message_encode_into(){
+        let hsms_header = HSMSHeader{
+            session_id: SessionID {session_id:0xFFFF},
+            header_byte2: HeaderByte2 {header_byte2:0},
+            header_byte3: 0,
+            p_type: 0,
+            s_type: 0,
+            system_bytes: 0x11111111,
+        };
+        let hsms_message = HSMSMessage::try_new(hsms_header.clone(),vec![0x01,0x02]).unwrap();
+        let expected = vec![0x00,0x00,0x00,0x0C,0xFF,0xFF,0x00,0x00,0x00,0x00,0x11,0x11,0x11,0x011,0x01,0x02];
+
+        let mut buf = BytesMut::new();
+        hsms_message.encode_into(&mut buf);
+        hsms_message.encode_into(&mut buf);
+        assert_eq!(&buf[..16],&expected[..]);
+        assert_eq!(&buf[16..],&expected[..]);
+
+        let mut slice = [0u8;20];
+        assert_eq!(hsms_message.encode_into_slice(&mut slice),Ok(16));
+        assert_eq!(&slice[..16],&expected[..]);
+        assert_eq!(hsms_message.encode_into_slice(&mut slice[..15]),Err("Buffer too small"));
+
+        let message_text:&[u8] = &[0x01,0x02];
+        assert_eq!(HSMSMessage::try_new(hsms_header.clone(),message_text),Ok(hsms_message));
+        let hsms_message_ref = HSMSMessageRef::try_new(hsms_header,message_text).unwrap();
+        let mut buf = BytesMut::new();
+        hsms_message_ref.encode_into(&mut buf);
+        assert_eq!(&buf[..],&expected[..]);
+    }
+
+    #[test]
+    fn test_hsms_message_new_takes_ownership(){
+        let hsms_header = HSMSHeader::new(SessionType::LinktestReq,0,0,0,0,0,0,0,1);
+        let message_text = vec![0x01,0x02];
+        let ptr = message_text.as_ptr();
+        let hsms_message = HSMSMessage::new_unchecked(hsms_header,message_text);
+        assert!(core::ptr::eq(hsms_message.message_text.unwrap().as_ptr(),ptr));
     }
     #[test]
     fn test_hsms_message_from_bytes(){
         let hsms_message_from_bytes = HSMSMessage::from_bytes(
-            vec![0x0A,0x00,0x00,0x00,0xFF,0xFF,0x00,0x00,0x00,0x00,0x11,0x11,0x11,0x011]);
+            vec![0x00,0x00,0x00,0x0A,0xFF,0xFF,0x00,0x00,0x00,0x00,0x11,0x11,0x11,0x011]);
         let hsms_header = HSMSHeader{
             session_id: SessionID {session_id:0xFFFF},
             header_byte2: HeaderByte2 {header_byte2:0},
@@ -539,7 +1383,7 @@ mod tests{
     #[test]
     fn test_hsms_message_from_bytes_with_message(){
        let hsms_message_from_bytes = HSMSMessage::from_bytes(
-           vec![0x0C,0x00,0x00,0x00,0xFF,0xFF,0x00,0x00,0x00,0x00,0x11,0x11,0x11,0x011,0x01,0x02]) ;
+           vec![0x00,0x00,0x00,0x0C,0xFF,0xFF,0x00,0x00,0x00,0x00,0x11,0x11,0x11,0x011,0x01,0x02]) ;
 
         let hsms_header = HSMSHeader{
             session_id: SessionID {session_id:0xFFFF},
@@ -557,5 +1401,224 @@ mod tests{
 
         let hsms_message_bytes = hsms_message.to_bytes();
         assert_eq!(hsms_message,hsms_message_from_bytes.unwrap());
+        assert_eq!(hsms_message_bytes,vec![0x00,0x00,0x00,0x0C,0xFF,0xFF,0x00,0x00,0x00,0x00,0x11,0x11,0x11,0x011,0x01,0x02]);
+    }
+
+    #[test]
+    fn test_hsms_message_ref_from_bytes(){
+        let bytes:Vec<u8> = vec![0x00,0x00,0x00,0x0C,0xFF,0xFF,0x00,0x00,0x00,0x00,0x11,0x11,0x11,0x011,0x01,0x02];
+        let hsms_message_ref = HSMSMessageRef::from_bytes(&bytes).unwrap();
+        assert_eq!(hsms_message_ref.message_length(),12);
+        assert_eq!(hsms_message_ref.hsms_header().system_bytes,0x11111111);
+        let message_text = hsms_message_ref.message_text().unwrap();
+        assert_eq!(message_text,&[0x01,0x02]);
+        assert!(core::ptr::eq(message_text.as_ptr(),bytes[14..].as_ptr()));
+        assert_eq!(hsms_message_ref.to_owned_message(),HSMSMessage::from_bytes(bytes.clone()).unwrap());
+
+        let hsms_message_ref = HSMSMessageRef::from_bytes(&bytes[..14]).unwrap();
+        assert_eq!(hsms_message_ref.message_text(),None);
+        assert_eq!(HSMSMessageRef::from_bytes(&bytes[..13]),Err("Size less than 14"));
+    }
+
+    #[test]
+    fn test_decode_mode(){
+        let valid:Vec<u8> = vec![0x00,0x00,0x00,0x0C,0xFF,0xFF,0x00,0x00,0x00,0x00,0x11,0x11,0x11,0x011,0x01,0x02];
+        for mode in [DecodeMode::Strict,DecodeMode::Lenient]{
+            let (_,violations) = HSMSMessage::from_bytes_with_mode(valid.clone(),mode).unwrap();
+            assert!(violations.is_empty());
+        }
+
+        //长度字段写10，实际带了2字节文本；PType为1；SType为8
+        let invalid:Vec<u8> = vec![0x00,0x00,0x00,0x0A,0xFF,0xFF,0x00,0x00,0x01,0x08,0x11,0x11,0x11,0x011,0x01,0x02];
+        let (hsms_message,violations) = HSMSMessage::from_bytes_with_mode(invalid.clone(),DecodeMode::Lenient).unwrap();
+        assert_eq!(hsms_message,HSMSMessage::from_bytes(invalid.clone()).unwrap());
+        assert_eq!(violations,vec![
+            Violation::LengthMismatch{declared:10,actual:12},
+            Violation::ReservedSessionType(8),
+            Violation::NonZeroPType(1),
+        ]);
+        let error = HSMSMessage::from_bytes_with_mode(invalid,DecodeMode::Strict).unwrap_err();
+        assert_eq!(error,FrameError::Violations(violations));
+        assert_eq!(error.to_string(),"Message length mismatch, Reserved session type, Nonzero PType");
+        assert_eq!(HSMSMessage::from_bytes_with_mode(valid[..13].to_vec(),DecodeMode::Strict),
+                   Err(FrameError::Malformed("Size less than 14")));
+    }
+
+    #[test]
+    fn test_quirks_little_endian_length(){
+        let bytes:Vec<u8> = vec![0x0C,0x00,0x00,0x00,0xFF,0xFF,0x00,0x00,0x00,0x00,0x11,0x11,0x11,0x011,0x01,0x02];
+        let quirks = Quirks{little_endian_length:true};
+        let hsms_message_ref = HSMSMessageRef::from_bytes_with_quirks(&bytes,&quirks).unwrap();
+        assert_eq!(hsms_message_ref.message_length(),12);
+        assert!(hsms_message_ref.validate().is_empty());
+        let hsms_message = HSMSMessage::from_bytes_with_quirks(bytes.clone(),&quirks).unwrap();
+        assert_eq!(hsms_message,hsms_message_ref.to_owned_message());
+
+        let hsms_message_ref = HSMSMessageRef::from_bytes_with_quirks(&bytes,&Quirks::default()).unwrap();
+        assert_eq!(hsms_message_ref.message_length(),0x0C000000);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "stream 200 exceeds 7 bits")]
+    fn test_data_message_stream_range(){
+        HSMSHeader::data_message(Role::Host,DeviceId::new(1).unwrap(),StreamFunction(200,1),true,1);
+    }
+
+    #[test]
+    fn test_w_bit_validation(){
+        //S1F2 W
+        let secondary_with_w_bit:Vec<u8> = vec![0x00,0x00,0x00,0x0A,0x00,0x01,0x81,0x02,0x00,0x00,0x00,0x00,0x00,0x01];
+        let (_,violations) = HSMSMessageRef::from_bytes_with_mode(&secondary_with_w_bit,DecodeMode::Lenient).unwrap();
+        assert_eq!(violations,vec![Violation::SecondaryWithWBit(S1F2)]);
+        assert_eq!(HSMSMessageRef::from_bytes_with_mode(&secondary_with_w_bit,DecodeMode::Strict),
+                   Err(FrameError::Violations(violations)));
+
+        let primary = HSMSHeader::new(SessionType::SECS2,0,0,1,0,0x80,1,1,1);
+        let primary_no_reply = HSMSHeader::new(SessionType::SECS2,0,0,1,0,0,1,1,1);
+        let reply = HSMSHeader::new(SessionType::SECS2,0,0x8000,1,0,0,1,2,1);
+        assert_eq!(reply.validate_reply(&primary),None);
+        assert_eq!(reply.validate_reply(&primary_no_reply),Some(Violation::ReplyToNoReplyPrimary(S1F2)));
+        //S1F1不是回复，即使primary的W-Bit为0
+        assert_eq!(primary.validate_reply(&primary_no_reply),None);
+        //Stream不同不是这条primary的回复
+        let s2f2 = HSMSHeader::new(SessionType::SECS2,0,0x8000,2,0,0,2,2,1);
+        assert_eq!(s2f2.validate_reply(&primary_no_reply),None);
+    }
+
+    #[test]
+    fn test_find_next_frame(){
+        let linktest_req:Vec<u8> = vec![0x00,0x00,0x00,0x0A,0xFF,0xFF,0x00,0x00,0x00,0x05,0x00,0x00,0x00,0x01];
+        let s1f1:Vec<u8> = vec![0x00,0x00,0x00,0x0A,0x00,0x01,0x81,0x01,0x00,0x00,0x00,0x00,0x00,0x02];
+        assert!(is_plausible_frame_start(&linktest_req,0xFFFF));
+        assert!(is_plausible_frame_start(&s1f1,0xFFFF));
+        assert!(!is_plausible_frame_start(&linktest_req[1..],0xFFFF));
+
+        //控制消息带文本不合理
+        let mut select_with_text = linktest_req.clone();
+        select_with_text[3] = 0x0C;
+        select_with_text.extend_from_slice(&[0x01,0x02]);
+        assert!(!is_plausible_frame_start(&select_with_text,0xFFFF));
+
+        let mut stream:Vec<u8> = vec![0x12,0x34,0x00,0x00,0x00];
+        stream.extend_from_slice(&s1f1);
+        stream.extend_from_slice(&linktest_req);
+        assert_eq!(find_next_frame(&stream,0xFFFF),Some(5));
+        assert_eq!(find_next_frame(&stream[6..],0x400),Some(13));
+        assert_eq!(find_next_frame(&[0xFF;32],0xFFFF),None);
+
+        //末尾的候选帧头不完整，返回其位置等待更多数据，而不是当作长度0
+        let mut partial:Vec<u8> = vec![0xFF,0xFF];
+        partial.extend_from_slice(&s1f1[..3]);
+        assert_eq!(find_next_frame(&partial,0xFFFF),Some(2));
+        partial.extend_from_slice(&s1f1[3..12]);
+        assert_eq!(find_next_frame(&partial,0xFFFF),Some(2));
+        assert_eq!(find_next_frame(&[0x00,0x00,0x00,0x09],0xFFFF),Some(1));
+    }
+
+    #[test]
+    fn test_control_message_text(){
+        let select_req = HSMSHeader::new(SessionType::SelectReq,0,0,0,0,0,0,0,1);
+        assert!(HSMSMessage::try_new(select_req.clone(),Vec::new()).is_ok());
+        assert_eq!(HSMSMessage::try_new(select_req.clone(),vec![0x01]),Err(EncodeError::ControlMessageWithText(1)));
+        assert_eq!(HSMSMessageRef::try_new(select_req.clone(),&[0x01]),Err(EncodeError::ControlMessageWithText(1)));
+        let s1f1 = HSMSHeader::new(SessionType::SECS2,0,0,1,0,0x80,1,1,1);
+        assert!(HSMSMessage::try_new(s1f1,vec![0x01]).is_ok());
+
+        //诊断重放时仍然可以构造，解码时会被标记
+        let bytes = HSMSMessage::new_unchecked(select_req.clone(),vec![0x01]).to_bytes();
+        let (_,violations) = HSMSMessageRef::from_bytes_with_mode(&bytes,DecodeMode::Lenient).unwrap();
+        assert_eq!(violations,vec![Violation::ControlMessageWithText(1)]);
+        assert_eq!(HSMSMessageRef::from_bytes_with_mode(&bytes,DecodeMode::Strict),Err(FrameError::Violations(violations)));
+
+        //长度字段为u32，消息头10字节加文本不能溢出
+        assert_eq!(message_length(&select_req,(u32::MAX-10) as usize),Some(u32::MAX));
+        assert_eq!(message_length(&select_req,(u32::MAX-9) as usize),None);
+    }
+
+    #[test]
+    fn test_decode_never_panics(){
+        let mut seed:u32 = 0x8765_4321;
+        let mut bytes:Vec<u8> = vec![0x00,0x00,0x00,0x0C,0xFF,0xFF,0x00,0x00,0x00,0x07,0x11,0x11,0x11,0x011,0x01,0x02];
+        bytes.extend((0..256).map(|_|{
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed>>16) as u8
+        }));
+        for start in 0..bytes.len(){
+            for end in start..bytes.len().min(start+40){
+                let input = &bytes[start..end];
+                let _ = HSMSMessage::from_bytes(input.to_vec());
+                let _ = HSMSMessageRef::from_bytes_with_mode(input,DecodeMode::Strict);
+                let _ = HSMSMessageRef::from_bytes_with_mode(input,DecodeMode::Lenient);
+                let _ = find_next_frame(input,0xFFFF_FFFF);
+                let _ = hexdump(input);
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_decode_body(){
+        let device_id = DeviceId::new(1).unwrap();
+        let item = Item::list().ascii("MDLN").ascii("1.0").build();
+        let s1f13 = HSMSHeader::data_message(Role::Host,device_id,S1F13,true,1);
+        let hsms_message = HSMSMessage::encode_body(s1f13,&item).unwrap();
+        assert_eq!(hsms_message.message_text(),Some(&[0x01,0x02,0x41,0x04,b'M',b'D',b'L',b'N',0x41,0x03,b'1',b'.',b'0'][..]));
+        assert_eq!(hsms_message.decode_body(),Ok(Some(item.clone())));
+
+        let hsms_message = HSMSMessage::from_bytes(hsms_message.to_bytes()).unwrap();
+        assert_eq!(hsms_message.decode_body(),Ok(Some(item.clone())));
+
+        let s1f1 = HSMSHeader::data_message(Role::Host,device_id,S1F1,true,2);
+        let hsms_message = HSMSMessage::from_bytes(HSMSMessage::from_header(s1f1).to_bytes()).unwrap();
+        assert_eq!(hsms_message.decode_body(),Ok(None));
+
+        let bytes:Vec<u8> = vec![0x00,0x00,0x00,0x0C,0x00,0x01,0x00,0x00,0x80,0x00,0x00,0x00,0x00,0x01,0x01,0x00];
+        assert_eq!(HSMSMessageRef::from_bytes(&bytes).unwrap().decode_body(),Err(DecodeError::UnsupportedPType(0x80)));
+        let bytes:Vec<u8> = vec![0x00,0x00,0x00,0x0C,0x00,0x01,0x81,0x0D,0x00,0x00,0x00,0x00,0x00,0x01,0x01,0x01];
+        assert_eq!(HSMSMessageRef::from_bytes(&bytes).unwrap().decode_body(),Err(DecodeError::UnexpectedEof{offset:2}));
+
+        //控制消息既不能编码也不能解码消息文本
+        let linktest_req = HSMSHeader::new(SessionType::LinktestReq,0xFFFF,0,0,0,0,0,0,3);
+        assert_eq!(HSMSMessage::encode_body(linktest_req.clone(),&item),Err(EncodeError::NotDataMessage(5)));
+        assert_eq!(HSMSMessage::from_header(linktest_req).decode_body(),Err(DecodeError::NotDataMessage(5)));
+    }
+
+    #[test]
+    fn test_hexdump(){
+        let dump = hexdump(&[0x00,0x00,0x00,0x0C,0x80,0x01,0x81,0x0D,0x00,0x00,0x11,0x11,0x11,0x011,0x01,0x02]);
+        let lines:Vec<&str> = dump.lines().map(|line|line.trim_end()).collect();
+        assert_eq!(lines,vec![
+            "00000000  00 00 00 0c                                      Message Length  12",
+            "00000004  80 01                                            Session ID      0x8001 (direction 1, device 1)",
+            "00000006  81                                               Header Byte 2   0x81 (W-bit 1, S1)",
+            "00000007  0d                                               Header Byte 3   0x0D (F13)",
+            "00000008  00                                               PType           0",
+            "00000009  00                                               SType           0 (SECS2)",
+            "0000000a  11 11 11 11                                      System Bytes    0x11111111",
+            "0000000e  01 02                                            Message Text    (2 bytes)",
+        ]);
+    }
+    #[test]
+    fn test_hexdump_truncated(){
+        let dump = hexdump(&[0x0A,0x00,0x00]);
+        assert_eq!(dump.trim_end(),"00000000  0a 00 00                                         Truncated frame");
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn test_arbitrary_hsms_message_round_trip(){
+        use arbitrary::{Arbitrary, Unstructured};
+
+        let mut seed:u32 = 0x1234_5678;
+        let data:Vec<u8> = (0..4096).map(|_|{
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed>>16) as u8
+        }).collect();
+        let mut u = Unstructured::new(&data);
+        while !u.is_empty(){
+            let hsms_message = HSMSMessage::arbitrary(&mut u).unwrap();
+            let hsms_message_from_bytes = HSMSMessage::from_bytes(hsms_message.to_bytes()).unwrap();
+            assert_eq!(hsms_message,hsms_message_from_bytes);
+        }
     }
 }
@@ -0,0 +1,18 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod extension;
+pub mod hsms;
+pub mod secs2;
+pub mod sml;
+pub mod stream_function;
+pub mod wire;
+#[cfg(feature = "std")]
+pub mod codec;
+#[cfg(feature = "std")]
+pub mod connection;
+#[cfg(feature = "std")]
+pub mod passive_server;
+#[cfg(feature = "std")]
+pub mod utils;
@@ -1,7 +1,3 @@
-mod hsms;
-mod passive_server;
-mod utils;
-
 fn main() {
     println!("Hello, world!");
 }
@@ -0,0 +1,157 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use crate::codec::HsmsCodec;
+use crate::connection::HsmsConnection;
+use crate::utils::Error;
+
+/*
+ * @brief ServerConfig
+ * t7 NOT SELECTED超时：TCP建立后在t7内没有收到Select.req则断开
+ * codec 每个连接使用的编解码配置（最大长度、Quirks）
+ */
+#[derive(Debug,Clone)]
+pub struct ServerConfig{
+    pub t7:Duration,
+    pub codec:HsmsCodec,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig{t7:Duration::from_secs(10),codec:HsmsCodec::new()}
+    }
+}
+
+/*
+ * @brief HsmsServer
+ * 被动模式（设备端）监听：
+ *   let server = HsmsServer::bind("0.0.0.0:5000",ServerConfig::default()).await?;
+ *   let (connection,peer) = server.accept().await?;
+ * accept返回时连接已经处于SELECTED，可以直接收发数据消息
+ * 每个新连接的Select握手在单独的任务中进行，一个迟迟不发Select.req的连接不会挡住其他连接，
+ * accept返回最先进入SELECTED的连接，其余握手继续进行，留给下一次accept
+ * t7超时或Select前断开的连接直接丢弃；HsmsServer被drop时未完成的握手一并取消
+ */
+#[derive(Debug)]
+pub struct HsmsServer{
+    listener:TcpListener,
+    config:ServerConfig,
+    handshakes:Mutex<JoinSet<Option<Accepted>>>,
+}
+
+//握手完成、已经SELECTED的连接和对端地址
+pub type Accepted = (HsmsConnection<TcpStream>,SocketAddr);
+
+impl HsmsServer {
+    pub async fn bind(addr:impl ToSocketAddrs,config:ServerConfig)->Result<HsmsServer,Error>{
+        let listener = TcpListener::bind(addr).await?;
+        Ok(HsmsServer{listener,config,handshakes:Mutex::new(JoinSet::new())})
+    }
+
+    pub fn local_addr(&self)->Result<SocketAddr,Error>{
+        Ok(self.listener.local_addr()?)
+    }
+
+    pub fn config(&self)->&ServerConfig{
+        &self.config
+    }
+
+    //只有监听本身出错时返回Err，单个连接的错误只丢弃该连接
+    pub async fn accept(&self)->Result<Accepted,Error>{
+        let mut handshakes = self.handshakes.lock().await;
+        loop {
+            tokio::select! {
+                accepted = self.listener.accept() => {
+                    let (stream,peer) = accepted?;
+                    if stream.set_nodelay(true).is_err(){
+                        continue;
+                    }
+                    let mut connection = HsmsConnection::with_codec(stream,self.config.codec.clone());
+                    let t7 = self.config.t7;
+                    handshakes.spawn(async move {
+                        match tokio::time::timeout(t7,connection.wait_selected()).await {
+                            Ok(Ok(())) => Some((connection,peer)),
+                            _ => {
+                                connection.close().await;
+                                None
+                            }
+                        }
+                    });
+                }
+                Some(joined) = handshakes.join_next() => {
+                    if let Ok(Some(selected)) = joined{
+                        return Ok(selected);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::connection::ConnectionState;
+    use crate::hsms::{DeviceId, HSMSHeader, HSMSMessage, Role};
+    use crate::stream_function::S1F1;
+
+    #[tokio::test]
+    async fn test_accept_selected_session(){
+        let server = HsmsServer::bind("127.0.0.1:0",ServerConfig::default()).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let client = tokio::spawn(async move {
+            let mut host = HsmsConnection::connect(addr).await?;
+            host.select().await?;
+            let s1f1 = HSMSHeader::data_message(Role::Host,DeviceId::new(1).unwrap(),S1F1,true,2);
+            host.send(&HSMSMessage::from_header(s1f1)).await?;
+            host.receive().await
+        });
+
+        let (mut equipment,_) = server.accept().await.unwrap();
+        assert_eq!(equipment.state(),ConnectionState::Selected);
+        let s1f1 = equipment.receive().await.unwrap().unwrap();
+        assert_eq!(s1f1.hsms_header().stream_function(),Some(S1F1));
+        equipment.separate().await.unwrap();
+        assert_eq!(client.await.unwrap().unwrap(),None);
+    }
+
+    #[tokio::test]
+    async fn test_idle_client_does_not_block_accept(){
+        let config = ServerConfig{t7:Duration::from_secs(60),..ServerConfig::default()};
+        let server = HsmsServer::bind("127.0.0.1:0",config).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        //先建立一个不发Select.req的连接
+        let _idle = TcpStream::connect(addr).await.unwrap();
+        let client = tokio::spawn(async move {
+            let mut host = HsmsConnection::connect(addr).await?;
+            host.select().await?;
+            Ok::<_,Error>(host)
+        });
+
+        //握手串行进行时这里要等到idle的t7（60秒）超时
+        let (equipment,_) = tokio::time::timeout(Duration::from_secs(5),server.accept()).await.unwrap().unwrap();
+        assert_eq!(equipment.state(),ConnectionState::Selected);
+        assert_eq!(client.await.unwrap().unwrap().state(),ConnectionState::Selected);
+    }
+
+    #[tokio::test]
+    async fn test_t7_timeout_drops_connection(){
+        let config = ServerConfig{t7:Duration::from_millis(50),..ServerConfig::default()};
+        let server = HsmsServer::bind("127.0.0.1:0",config).await.unwrap();
+        let addr = server.local_addr().unwrap();
+        let client = tokio::spawn(async move {
+            //第一个连接不发Select.req，t7超时后被服务端断开
+            let mut idle = HsmsConnection::connect(addr).await?;
+            assert_eq!(idle.receive().await?,None);
+            let mut host = HsmsConnection::connect(addr).await?;
+            host.select().await?;
+            Ok::<_,Error>(host)
+        });
+
+        let (equipment,_) = server.accept().await.unwrap();
+        assert_eq!(equipment.state(),ConnectionState::Selected);
+        assert_eq!(client.await.unwrap().unwrap().state(),ConnectionState::Selected);
+    }
+}
@@ -0,0 +1,523 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use num_enum::{IntoPrimitive, TryFromPrimitive};
+use crate::wire;
+
+/*
+ * @brief SECS-II Item (SEMI E5)
+ * 每个Item由格式字节、长度字节和数据组成
+ * 格式字节 高6位为格式码，低2位为长度字节数(1-3)
+ * 0000 0000
+ * ^^^^ ^^
+ * 格式码  长度字节数
+ * 长度字节为大端，表示数据的字节数；L类型表示子Item的个数
+ * 数值类型按大端编码
+ */
+
+/*
+ * @brief FormatCode
+ * 格式码（八进制）
+ * 00  L        列表
+ * 10  B        二进制
+ * 11  BOOLEAN  布尔
+ * 20  A        ASCII
+ * 21  J        JIS-8
+ * 30  I8       8字节有符号整数
+ * 31  I1       1字节有符号整数
+ * 32  I2       2字节有符号整数
+ * 34  I4       4字节有符号整数
+ * 40  F8       8字节浮点
+ * 44  F4       4字节浮点
+ * 50  U8       8字节无符号整数
+ * 51  U1       1字节无符号整数
+ * 52  U2       2字节无符号整数
+ * 54  U4       4字节无符号整数
+ */
+#[derive(Debug,Clone,Copy,Eq, PartialEq,IntoPrimitive,TryFromPrimitive)]
+#[repr(u8)]
+pub enum FormatCode{
+    L = 0o00,
+    B = 0o10,
+    Boolean = 0o11,
+    A = 0o20,
+    J = 0o21,
+    I8 = 0o30,
+    I1 = 0o31,
+    I2 = 0o32,
+    I4 = 0o34,
+    F8 = 0o40,
+    F4 = 0o44,
+    U8 = 0o50,
+    U1 = 0o51,
+    U2 = 0o52,
+    U4 = 0o54,
+}
+
+#[derive(Debug,Clone,PartialEq)]
+pub enum Item{
+    L(Vec<Item>),
+    B(Vec<u8>),
+    Boolean(Vec<bool>),
+    //ASCII按原始字节保存，设备常在A里放Latin-1或厂商自定义字节，解码时不做校验
+    A(Vec<u8>),
+    J(Vec<u8>),
+    I1(Vec<i8>),
+    I2(Vec<i16>),
+    I4(Vec<i32>),
+    I8(Vec<i64>),
+    U1(Vec<u8>),
+    U2(Vec<u16>),
+    U4(Vec<u32>),
+    U8(Vec<u64>),
+    F4(Vec<f32>),
+    F8(Vec<f64>),
+}
+
+//长度字段最多3字节
+pub const MAX_ITEM_LENGTH:usize = 0xFF_FFFF;
+//解码时允许的最大列表嵌套深度，防止恶意数据导致栈溢出
+pub const MAX_DEPTH:usize = 64;
+
+#[derive(Debug,Clone,Eq, PartialEq)]
+pub enum EncodeError{
+    //数据字节数或列表元素个数超过3字节长度能表示的范围
+    ItemTooLong(usize),
+    //SType不为0，不能编码SECS-II消息文本
+    NotDataMessage(u8),
+    //控制消息（SType不为0）带了消息文本
+    ControlMessageWithText(u8),
+    //消息头加消息文本超过4字节长度字段能表示的范围
+    TextTooLong(usize),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::ItemTooLong(length) => write!(f,"Item length {} exceeds {}",length,MAX_ITEM_LENGTH),
+            EncodeError::NotDataMessage(s_type) => write!(f,"SType {} is not a data message",s_type),
+            EncodeError::ControlMessageWithText(s_type) => write!(f,"Control message SType {} with text",s_type),
+            EncodeError::TextTooLong(length) => write!(f,"Message text length {} exceeds the length field",length),
+        }
+    }
+}
+
+#[derive(Debug,Clone,Eq, PartialEq)]
+pub enum DecodeError{
+    //数据不完整，offset为出错位置
+    UnexpectedEof{offset:usize},
+    InvalidFormatCode{offset:usize,format_byte:u8},
+    //长度字节数为0
+    InvalidLengthBytes{offset:usize},
+    //数据长度不是元素大小的整数倍
+    InvalidLength{offset:usize,format_code:FormatCode,length:usize},
+    TooDeep{offset:usize},
+    //单个Item解码后还有剩余字节
+    TrailingBytes{offset:usize},
+    //PType不为0，消息文本不是SECS-II
+    UnsupportedPType(u8),
+    //SType不为0，不是数据消息
+    NotDataMessage(u8),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEof{offset} => write!(f,"Unexpected end of data at offset {}",offset),
+            DecodeError::InvalidFormatCode{offset,format_byte} =>
+                write!(f,"Invalid format byte 0x{:02X} at offset {}",format_byte,offset),
+            DecodeError::InvalidLengthBytes{offset} => write!(f,"Zero length bytes at offset {}",offset),
+            DecodeError::InvalidLength{offset,format_code,length} =>
+                write!(f,"Invalid length {} for {:?} at offset {}",length,format_code,offset),
+            DecodeError::TooDeep{offset} => write!(f,"List nesting deeper than {} at offset {}",MAX_DEPTH,offset),
+            DecodeError::TrailingBytes{offset} => write!(f,"Trailing bytes at offset {}",offset),
+            DecodeError::UnsupportedPType(p_type) => write!(f,"PType {} is not SECS-II",p_type),
+            DecodeError::NotDataMessage(s_type) => write!(f,"SType {} is not a data message",s_type),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for EncodeError {}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+impl Item {
+    pub fn list()->ListBuilder{
+        ListBuilder::default()
+    }
+
+    pub fn ascii(text:&str)->Item{
+        Item::A(text.as_bytes().to_vec())
+    }
+
+    //A的文本，字节不是合法的UTF-8或不是A时返回None
+    pub fn as_str(&self)->Option<&str>{
+        match self {
+            Item::A(text) => core::str::from_utf8(text).ok(),
+            _ => None,
+        }
+    }
+
+    pub fn format_code(&self)->FormatCode{
+        match self {
+            Item::L(_) => FormatCode::L,
+            Item::B(_) => FormatCode::B,
+            Item::Boolean(_) => FormatCode::Boolean,
+            Item::A(_) => FormatCode::A,
+            Item::J(_) => FormatCode::J,
+            Item::I1(_) => FormatCode::I1,
+            Item::I2(_) => FormatCode::I2,
+            Item::I4(_) => FormatCode::I4,
+            Item::I8(_) => FormatCode::I8,
+            Item::U1(_) => FormatCode::U1,
+            Item::U2(_) => FormatCode::U2,
+            Item::U4(_) => FormatCode::U4,
+            Item::U8(_) => FormatCode::U8,
+            Item::F4(_) => FormatCode::F4,
+            Item::F8(_) => FormatCode::F8,
+        }
+    }
+
+    //长度字段的值：列表为子Item个数，其余为数据字节数
+    pub fn length(&self)->usize{
+        match self {
+            Item::L(items) => items.len(),
+            Item::B(values) => values.len(),
+            Item::Boolean(values) => values.len(),
+            Item::A(text) => text.len(),
+            Item::J(values) => values.len(),
+            Item::I1(values) => values.len(),
+            Item::I2(values) => values.len()*2,
+            Item::I4(values) => values.len()*4,
+            Item::I8(values) => values.len()*8,
+            Item::U1(values) => values.len(),
+            Item::U2(values) => values.len()*2,
+            Item::U4(values) => values.len()*4,
+            Item::U8(values) => values.len()*8,
+            Item::F4(values) => values.len()*4,
+            Item::F8(values) => values.len()*8,
+        }
+    }
+
+    pub fn encode(&self)->Result<Vec<u8>,EncodeError>{
+        let mut vec = Vec::new();
+        self.encode_into(&mut vec)?;
+        Ok(vec)
+    }
+
+    pub fn encode_into(&self,buf:&mut Vec<u8>)->Result<(),EncodeError>{
+        let length = self.length();
+        if length>MAX_ITEM_LENGTH{
+            return Err(EncodeError::ItemTooLong(length));
+        }
+        let format_code:u8 = self.format_code().into();
+        let length_bytes = (length as u32).to_be_bytes();
+        let num_length_bytes = match length {
+            0..=0xFF => 1,
+            0x100..=0xFFFF => 2,
+            _ => 3,
+        };
+        buf.push(format_code<<2|num_length_bytes as u8);
+        buf.extend_from_slice(&length_bytes[4-num_length_bytes..]);
+        match self {
+            Item::L(items) => {
+                for item in items{
+                    item.encode_into(buf)?;
+                }
+            }
+            Item::B(values)|Item::A(values)|Item::J(values)|Item::U1(values) => buf.extend_from_slice(values),
+            Item::Boolean(values) => buf.extend(values.iter().map(|value|*value as u8)),
+            Item::I1(values) => buf.extend(values.iter().map(|value|*value as u8)),
+            Item::I2(values) => wire::write_values(values,buf),
+            Item::I4(values) => wire::write_values(values,buf),
+            Item::I8(values) => wire::write_values(values,buf),
+            Item::U2(values) => wire::write_values(values,buf),
+            Item::U4(values) => wire::write_values(values,buf),
+            Item::U8(values) => wire::write_values(values,buf),
+            Item::F4(values) => wire::write_values(values,buf),
+            Item::F8(values) => wire::write_values(values,buf),
+        }
+        Ok(())
+    }
+
+    //解码一个完整的Item，bytes必须恰好是一个Item
+    pub fn decode(bytes:&[u8])->Result<Item,DecodeError>{
+        let (item,consumed) = Item::decode_prefix(bytes)?;
+        if consumed!=bytes.len(){
+            return Err(DecodeError::TrailingBytes{offset:consumed});
+        }
+        Ok(item)
+    }
+
+    //从bytes开头解码一个Item，返回Item和消耗的字节数
+    pub fn decode_prefix(bytes:&[u8])->Result<(Item,usize),DecodeError>{
+        let mut offset = 0;
+        let item = decode_item(bytes,&mut offset,0)?;
+        Ok((item,offset))
+    }
+}
+
+/*
+ * @brief ListBuilder
+ * 链式构造嵌套列表，长度字节在编码时自动计算
+ * Item::list().ascii("PPID").u4(1).list(|l| l.u1(0).boolean(true)).build()
+ * 数值方法添加单个值的Item，需要数组时用item()传入完整的Item
+ */
+#[derive(Debug,Clone,Default,PartialEq)]
+pub struct ListBuilder{
+    items:Vec<Item>,
+}
+
+impl ListBuilder {
+    pub fn item(mut self,item:impl Into<Item>)->ListBuilder{
+        self.items.push(item.into());
+        self
+    }
+
+    pub fn list(self,build:impl FnOnce(ListBuilder)->ListBuilder)->ListBuilder{
+        let list = build(ListBuilder::default());
+        self.item(list)
+    }
+
+    pub fn binary(self,value:u8)->ListBuilder{
+        self.item(Item::B(vec![value]))
+    }
+
+    pub fn boolean(self,value:bool)->ListBuilder{
+        self.item(Item::Boolean(vec![value]))
+    }
+
+    pub fn ascii(self,text:&str)->ListBuilder{
+        self.item(Item::ascii(text))
+    }
+
+    pub fn i1(self,value:i8)->ListBuilder{
+        self.item(Item::I1(vec![value]))
+    }
+
+    pub fn i2(self,value:i16)->ListBuilder{
+        self.item(Item::I2(vec![value]))
+    }
+
+    pub fn i4(self,value:i32)->ListBuilder{
+        self.item(Item::I4(vec![value]))
+    }
+
+    pub fn i8(self,value:i64)->ListBuilder{
+        self.item(Item::I8(vec![value]))
+    }
+
+    pub fn u1(self,value:u8)->ListBuilder{
+        self.item(Item::U1(vec![value]))
+    }
+
+    pub fn u2(self,value:u16)->ListBuilder{
+        self.item(Item::U2(vec![value]))
+    }
+
+    pub fn u4(self,value:u32)->ListBuilder{
+        self.item(Item::U4(vec![value]))
+    }
+
+    pub fn u8(self,value:u64)->ListBuilder{
+        self.item(Item::U8(vec![value]))
+    }
+
+    pub fn f4(self,value:f32)->ListBuilder{
+        self.item(Item::F4(vec![value]))
+    }
+
+    pub fn f8(self,value:f64)->ListBuilder{
+        self.item(Item::F8(vec![value]))
+    }
+
+    pub fn build(self)->Item{
+        Item::L(self.items)
+    }
+}
+
+impl From<ListBuilder> for Item {
+    fn from(builder: ListBuilder) -> Self {
+        builder.build()
+    }
+}
+
+fn decode_item(bytes:&[u8],offset:&mut usize,depth:usize)->Result<Item,DecodeError>{
+    let start = *offset;
+    let format_byte = *bytes.get(start).ok_or(DecodeError::UnexpectedEof{offset:start})?;
+    let format_code = FormatCode::try_from(format_byte>>2)
+        .map_err(|_|DecodeError::InvalidFormatCode{offset:start,format_byte})?;
+    let num_length_bytes = (format_byte&0x03) as usize;
+    if num_length_bytes==0{
+        return Err(DecodeError::InvalidLengthBytes{offset:start});
+    }
+    let length_bytes = bytes.get(start+1..start+1+num_length_bytes)
+        .ok_or(DecodeError::UnexpectedEof{offset:start+1})?;
+    let length = length_bytes.iter().fold(0usize,|length,byte|length<<8|*byte as usize);
+    *offset = start+1+num_length_bytes;
+
+    if format_code==FormatCode::L{
+        if depth>=MAX_DEPTH{
+            return Err(DecodeError::TooDeep{offset:start});
+        }
+        //元素个数来自对端数据，预分配不超过剩余字节数
+        let mut items = Vec::with_capacity(length.min(bytes.len()-*offset));
+        for _ in 0..length{
+            items.push(decode_item(bytes,offset,depth+1)?);
+        }
+        return Ok(Item::L(items));
+    }
+
+    let data_offset = *offset;
+    let data = bytes.get(data_offset..data_offset+length)
+        .ok_or(DecodeError::UnexpectedEof{offset:data_offset})?;
+    *offset = data_offset+length;
+    let element_size = match format_code {
+        FormatCode::I2|FormatCode::U2 => 2,
+        FormatCode::I4|FormatCode::U4|FormatCode::F4 => 4,
+        FormatCode::I8|FormatCode::U8|FormatCode::F8 => 8,
+        _ => 1,
+    };
+    if length%element_size!=0{
+        return Err(DecodeError::InvalidLength{offset:start,format_code,length});
+    }
+    let item = match format_code {
+        FormatCode::L => unreachable!(),
+        FormatCode::B => Item::B(data.to_vec()),
+        FormatCode::Boolean => Item::Boolean(data.iter().map(|byte|*byte!=0).collect()),
+        FormatCode::A => Item::A(data.to_vec()),
+        FormatCode::J => Item::J(data.to_vec()),
+        FormatCode::I1 => Item::I1(data.iter().map(|byte|*byte as i8).collect()),
+        FormatCode::I2 => Item::I2(wire::read_values(data)),
+        FormatCode::I4 => Item::I4(wire::read_values(data)),
+        FormatCode::I8 => Item::I8(wire::read_values(data)),
+        FormatCode::U1 => Item::U1(data.to_vec()),
+        FormatCode::U2 => Item::U2(wire::read_values(data)),
+        FormatCode::U4 => Item::U4(wire::read_values(data)),
+        FormatCode::U8 => Item::U8(wire::read_values(data)),
+        FormatCode::F4 => Item::F4(wire::read_values(data)),
+        FormatCode::F8 => Item::F8(wire::read_values(data)),
+    };
+    Ok(item)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_encode_scalars(){
+        assert_eq!(Item::U4(vec![1]).encode().unwrap(),vec![0xB1,0x04,0x00,0x00,0x00,0x01]);
+        assert_eq!(Item::I2(vec![-2]).encode().unwrap(),vec![0x69,0x02,0xFF,0xFE]);
+        assert_eq!(Item::U1(vec![]).encode().unwrap(),vec![0xA5,0x00]);
+        assert_eq!(Item::B(vec![0x00]).encode().unwrap(),vec![0x21,0x01,0x00]);
+        assert_eq!(Item::Boolean(vec![true,false]).encode().unwrap(),vec![0x25,0x02,0x01,0x00]);
+        assert_eq!(Item::ascii("OK").encode().unwrap(),vec![0x41,0x02,b'O',b'K']);
+        assert_eq!(Item::J(vec![0x41]).encode().unwrap(),vec![0x45,0x01,0x41]);
+        assert_eq!(Item::ascii("OK").as_str(),Some("OK"));
+        assert_eq!(Item::F4(vec![1.0]).encode().unwrap(),vec![0x91,0x04,0x3F,0x80,0x00,0x00]);
+        assert_eq!(Item::F8(vec![1.0]).encode().unwrap(),vec![0x81,0x08,0x3F,0xF0,0x00,0x00,0x00,0x00,0x00,0x00]);
+        assert_eq!(Item::I8(vec![-1]).encode().unwrap(),vec![0x61,0x08,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF]);
+        assert_eq!(Item::U8(vec![1]).encode().unwrap(),vec![0xA1,0x08,0x00,0x00,0x00,0x00,0x00,0x00,0x00,0x01]);
+        assert_eq!(Item::I1(vec![-1]).encode().unwrap(),vec![0x65,0x01,0xFF]);
+        assert_eq!(Item::I4(vec![-1]).encode().unwrap(),vec![0x71,0x04,0xFF,0xFF,0xFF,0xFF]);
+        assert_eq!(Item::U2(vec![0x0102]).encode().unwrap(),vec![0xA9,0x02,0x01,0x02]);
+    }
+
+    #[test]
+    fn test_encode_list(){
+        //S1F13 <L <A "MDLN"> <A "1.0">>
+        let item = Item::L(vec![Item::ascii("MDLN"),Item::ascii("1.0")]);
+        let bytes = vec![0x01,0x02,0x41,0x04,b'M',b'D',b'L',b'N',0x41,0x03,b'1',b'.',b'0'];
+        assert_eq!(item.encode().unwrap(),bytes);
+        assert_eq!(Item::decode(&bytes).unwrap(),item);
+        assert_eq!(Item::L(vec![]).encode().unwrap(),vec![0x01,0x00]);
+    }
+
+    #[test]
+    fn test_list_builder(){
+        //S2F41 <L <A "START"> <L <L <A "PPID"> <A "RCP1">> <L <A "LOT"> <U4 7>>>>
+        let item = Item::list()
+            .ascii("START")
+            .list(|l| l
+                .list(|l| l.ascii("PPID").ascii("RCP1"))
+                .list(|l| l.ascii("LOT").u4(7)))
+            .build();
+        let expected = Item::L(vec![
+            Item::ascii("START"),
+            Item::L(vec![
+                Item::L(vec![Item::ascii("PPID"),Item::ascii("RCP1")]),
+                Item::L(vec![Item::ascii("LOT"),Item::U4(vec![7])]),
+            ]),
+        ]);
+        assert_eq!(item,expected);
+        assert_eq!(&item.encode().unwrap()[..2],&[0x01,0x02]);
+
+        let item:Item = Item::list().binary(1).boolean(true).i1(-1).i2(-2).i4(-4).i8(-8)
+            .u1(1).u2(2).u8(8).f4(0.5).f8(0.25).item(Item::U2(vec![1,2])).into();
+        assert_eq!(item.length(),12);
+        assert_eq!(Item::decode(&item.encode().unwrap()).unwrap(),item);
+        assert_eq!(Item::list().build(),Item::L(vec![]));
+    }
+
+    #[test]
+    fn test_length_bytes(){
+        let bytes = Item::B(vec![0;0x100]).encode().unwrap();
+        assert_eq!(&bytes[..3],&[0x22,0x01,0x00]);
+        let bytes = Item::B(vec![0;0x10000]).encode().unwrap();
+        assert_eq!(&bytes[..4],&[0x23,0x01,0x00,0x00]);
+        assert_eq!(Item::decode(&bytes).unwrap(),Item::B(vec![0;0x10000]));
+        assert_eq!(Item::B(vec![0;MAX_ITEM_LENGTH+1]).encode(),Err(EncodeError::ItemTooLong(MAX_ITEM_LENGTH+1)));
+        //长度字节数不是最短的也能解码
+        assert_eq!(Item::decode(&[0xB3,0x00,0x00,0x04,0x00,0x00,0x00,0x01]).unwrap(),Item::U4(vec![1]));
+    }
+
+    #[test]
+    fn test_round_trip_all_formats(){
+        let item = Item::L(vec![
+            Item::B(vec![0x01,0x02]),
+            Item::Boolean(vec![true]),
+            Item::ascii("PPID"),
+            Item::J(vec![0xB1]),
+            Item::I1(vec![-128,127]),
+            Item::I2(vec![i16::MIN,i16::MAX]),
+            Item::I4(vec![i32::MIN,i32::MAX]),
+            Item::I8(vec![i64::MIN,i64::MAX]),
+            Item::U1(vec![u8::MAX]),
+            Item::U2(vec![u16::MAX]),
+            Item::U4(vec![u32::MAX]),
+            Item::U8(vec![u64::MAX]),
+            Item::F4(vec![-1.5]),
+            Item::F8(vec![2.25]),
+            Item::L(vec![Item::L(vec![])]),
+        ]);
+        let bytes = item.encode().unwrap();
+        assert_eq!(Item::decode(&bytes).unwrap(),item);
+
+        //A里的Latin-1和控制字节原样保留
+        let item = Item::decode(&[0x41,0x02,0x01,0xB0]).unwrap();
+        assert_eq!(item,Item::A(vec![0x01,0xB0]));
+        assert_eq!(item.as_str(),None);
+        assert_eq!(item.encode().unwrap(),vec![0x41,0x02,0x01,0xB0]);
+    }
+
+    #[test]
+    fn test_decode_errors(){
+        assert_eq!(Item::decode(&[]),Err(DecodeError::UnexpectedEof{offset:0}));
+        assert_eq!(Item::decode(&[0xFD,0x00]),Err(DecodeError::InvalidFormatCode{offset:0,format_byte:0xFD}));
+        assert_eq!(Item::decode(&[0xB0,0x00]),Err(DecodeError::InvalidLengthBytes{offset:0}));
+        assert_eq!(Item::decode(&[0xB1,0x04,0x00]),Err(DecodeError::UnexpectedEof{offset:2}));
+        assert_eq!(Item::decode(&[0xB1,0x03,0x00,0x00,0x00]),
+                   Err(DecodeError::InvalidLength{offset:0,format_code:FormatCode::U4,length:3}));
+        assert_eq!(Item::decode(&[0x01,0x01]),Err(DecodeError::UnexpectedEof{offset:2}));
+        assert_eq!(Item::decode(&[0x01,0x00,0x01,0x00]),Err(DecodeError::TrailingBytes{offset:2}));
+        assert_eq!(Item::decode_prefix(&[0x01,0x00,0x01,0x00]),Ok((Item::L(vec![]),2)));
+
+        let deep:Vec<u8> = [0x01,0x01].repeat(MAX_DEPTH+1);
+        assert_eq!(Item::decode(&deep),Err(DecodeError::TooDeep{offset:MAX_DEPTH*2}));
+        //列表声明了很多元素但数据不足，不会预分配巨大内存
+        assert_eq!(Item::decode(&[0x03,0xFF,0xFF,0xFF]),Err(DecodeError::UnexpectedEof{offset:4}));
+    }
+}
@@ -0,0 +1,673 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::str::FromStr;
+use crate::hsms::{DeviceId, HSMSHeader, HSMSMessage, Role};
+use crate::secs2::{DecodeError, EncodeError, FormatCode, Item, MAX_DEPTH};
+use crate::stream_function::StreamFunction;
+
+/*
+ * @brief SML (SECS Message Language)
+ * 消息：SxFy [W] [Item] [.]
+ *   S1F13 W <L <A "MDLN"> <A "1.0">>.
+ * Item：<格式 [长度] 值...>
+ *   格式不区分大小写：L B BOOLEAN A J I1 I2 I4 I8 U1 U2 U4 U8 F4 F8
+ *   [长度]仅作提示，解析时忽略（各家工具对字节数/元素个数的写法不一致）
+ *   整数支持十进制、0x十六进制、0b二进制；BOOLEAN支持TRUE/FALSE或整数
+ *   A、J的值为双引号字符串，可写多段，支持 \" \\ \n \r \t 转义
+ * 结尾的'.'可省略
+ *
+ * Display输出带缩进的SML，每层列表缩进2个空格，[n]为元素个数：
+ *   S1F13 W
+ *   <L [2]
+ *     <A [4] "MDLN">
+ *     <A [3] "1.0">
+ *   >
+ *   .
+ * 输出可以被parse_message重新解析
+ */
+
+#[derive(Debug,Clone,Eq, PartialEq)]
+pub enum ParseErrorKind{
+    UnexpectedEof,
+    //expected为期望的内容
+    UnexpectedToken{expected:&'static str},
+    UnterminatedString,
+    InvalidStreamFunction,
+    UnknownFormat(String),
+    //值不能转换为该格式，如U1的256
+    InvalidValue(FormatCode),
+    TooDeep,
+}
+
+/*
+ * @brief ParseError
+ * offset为字节偏移，line/column从1开始（column按字符计）
+ */
+#[derive(Debug,Clone,Eq, PartialEq)]
+pub struct ParseError{
+    pub offset:usize,
+    pub line:usize,
+    pub column:usize,
+    pub kind:ParseErrorKind,
+}
+
+impl ParseError {
+    fn new(text:&str,offset:usize,kind:ParseErrorKind)->ParseError{
+        let before = &text[..offset];
+        let line = before.matches('\n').count()+1;
+        let line_start = before.rfind('\n').map(|index|index+1).unwrap_or(0);
+        let column = before[line_start..].chars().count()+1;
+        ParseError{offset,line,column,kind}
+    }
+}
+
+impl fmt::Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnexpectedEof => write!(f,"Unexpected end of input"),
+            ParseErrorKind::UnexpectedToken{expected} => write!(f,"Expected {}",expected),
+            ParseErrorKind::UnterminatedString => write!(f,"Unterminated string"),
+            ParseErrorKind::InvalidStreamFunction => write!(f,"Invalid stream/function"),
+            ParseErrorKind::UnknownFormat(format) => write!(f,"Unknown item format {}",format),
+            ParseErrorKind::InvalidValue(format_code) => write!(f,"Invalid value for {:?}",format_code),
+            ParseErrorKind::TooDeep => write!(f,"List nesting deeper than {}",MAX_DEPTH),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,"line {}, column {}: {}",self.line,self.column,self.kind)
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+/*
+ * @brief SmlMessage
+ * SML描述的数据消息，不含Session ID和System Bytes，
+ * 发送前用to_hsms_message补全
+ */
+#[derive(Debug,Clone,PartialEq)]
+pub struct SmlMessage{
+    pub stream_function:StreamFunction,
+    pub w_bit:bool,
+    pub item:Option<Item>,
+}
+
+impl SmlMessage {
+    pub fn to_hsms_message(&self,role:Role,device_id:DeviceId,system_bytes:u32)->Result<HSMSMessage,EncodeError>{
+        let hsms_header = HSMSHeader::data_message(role,device_id,self.stream_function,self.w_bit,system_bytes);
+        match &self.item {
+            Some(item) => HSMSMessage::encode_body(hsms_header,item),
+            None => Ok(HSMSMessage::from_header(hsms_header)),
+        }
+    }
+}
+
+pub fn parse_message(text:&str)->Result<SmlMessage,ParseError>{
+    let mut parser = Parser::new(text);
+    let message = parser.message()?;
+    parser.end()?;
+    Ok(message)
+}
+
+pub fn parse_item(text:&str)->Result<Item,ParseError>{
+    let mut parser = Parser::new(text);
+    let item = parser.item(0)?;
+    parser.end()?;
+    Ok(item)
+}
+
+//解码数据消息的消息文本，控制消息返回NotDataMessage
+impl TryFrom<&HSMSMessage> for SmlMessage {
+    type Error = DecodeError;
+
+    fn try_from(hsms_message: &HSMSMessage) -> Result<Self, Self::Error> {
+        let hsms_header = hsms_message.hsms_header();
+        let stream_function = hsms_header.stream_function()
+            .ok_or(DecodeError::NotDataMessage(hsms_header.s_type()))?;
+        Ok(SmlMessage{
+            stream_function,
+            w_bit:hsms_header.w_bit(),
+            item:hsms_message.decode_body()?,
+        })
+    }
+}
+
+impl fmt::Display for SmlMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,"{}",self.stream_function)?;
+        if self.w_bit{
+            write!(f," W")?;
+        }
+        match &self.item {
+            Some(item) => {
+                writeln!(f)?;
+                write_item(f,item,0)?;
+                write!(f,"\n.")
+            }
+            None => write!(f,"."),
+        }
+    }
+}
+
+impl fmt::Display for Item {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write_item(f,self,0)
+    }
+}
+
+fn write_item(f:&mut fmt::Formatter<'_>,item:&Item,depth:usize)->fmt::Result{
+    write!(f,"{:indent$}<","",indent=depth*2)?;
+    match item {
+        Item::L(items) => {
+            write!(f,"L [{}]",items.len())?;
+            if items.is_empty(){
+                return write!(f,">");
+            }
+            for item in items{
+                writeln!(f)?;
+                write_item(f,item,depth+1)?;
+            }
+            return write!(f,"\n{:indent$}>","",indent=depth*2);
+        }
+        Item::B(values) => {
+            write!(f,"B [{}]",values.len())?;
+            values.iter().try_for_each(|value|write!(f," 0x{:02X}",value))?;
+        }
+        Item::Boolean(values) => {
+            write!(f,"BOOLEAN [{}]",values.len())?;
+            values.iter().try_for_each(|value|write!(f," {}",if *value {"TRUE"} else {"FALSE"}))?;
+        }
+        Item::A(values) => write_text(f,"A",values)?,
+        Item::J(values) => write_text(f,"J",values)?,
+        Item::I1(values) => write_values(f,"I1",values)?,
+        Item::I2(values) => write_values(f,"I2",values)?,
+        Item::I4(values) => write_values(f,"I4",values)?,
+        Item::I8(values) => write_values(f,"I8",values)?,
+        Item::U1(values) => write_values(f,"U1",values)?,
+        Item::U2(values) => write_values(f,"U2",values)?,
+        Item::U4(values) => write_values(f,"U4",values)?,
+        Item::U8(values) => write_values(f,"U8",values)?,
+        Item::F4(values) => write_values(f,"F4",values)?,
+        Item::F8(values) => write_values(f,"F8",values)?,
+    }
+    write!(f,">")
+}
+
+fn write_values<T:fmt::Display>(f:&mut fmt::Formatter<'_>,format:&str,values:&[T])->fmt::Result{
+    write!(f,"{} [{}]",format,values.len())?;
+    values.iter().try_for_each(|value|write!(f," {}",value))
+}
+
+//A和JIS-8不一定是合法的UTF-8（Latin-1、厂商字节），此时按字节输出
+fn write_text(f:&mut fmt::Formatter<'_>,format:&str,values:&[u8])->fmt::Result{
+    write!(f,"{} [{}]",format,values.len())?;
+    match core::str::from_utf8(values) {
+        Ok(text) if !text.is_empty() => {
+            write!(f," ")?;
+            write_string(f,text)
+        }
+        Ok(_) => Ok(()),
+        Err(_) => values.iter().try_for_each(|value|write!(f," 0x{:02X}",value)),
+    }
+}
+
+fn write_string(f:&mut fmt::Formatter<'_>,text:&str)->fmt::Result{
+    write!(f,"\"")?;
+    for c in text.chars(){
+        match c {
+            '"' => write!(f,"\\\"")?,
+            '\\' => write!(f,"\\\\")?,
+            '\n' => write!(f,"\\n")?,
+            '\r' => write!(f,"\\r")?,
+            '\t' => write!(f,"\\t")?,
+            c => write!(f,"{}",c)?,
+        }
+    }
+    write!(f,"\"")
+}
+
+impl FromStr for SmlMessage {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        parse_message(text)
+    }
+}
+
+impl FromStr for Item {
+    type Err = ParseError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        parse_item(text)
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
+enum Token<'a>{
+    LAngle,
+    RAngle,
+    LBracket,
+    RBracket,
+    Dot,
+    Word(&'a str),
+    Str(String),
+}
+
+struct Tokenizer<'a>{
+    text:&'a str,
+    offset:usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    fn skip_whitespace(&mut self){
+        let rest = &self.text[self.offset..];
+        self.offset += rest.len()-rest.trim_start().len();
+    }
+
+    //返回下一个Token及其起始偏移，输入结束时返回None
+    fn next(&mut self)->Result<Option<(usize,Token<'a>)>,ParseError>{
+        self.skip_whitespace();
+        let start = self.offset;
+        let rest = &self.text[start..];
+        let Some(c) = rest.chars().next() else {
+            return Ok(None);
+        };
+        let token = match c {
+            '<' => Token::LAngle,
+            '>' => Token::RAngle,
+            '[' => Token::LBracket,
+            ']' => Token::RBracket,
+            '.' => Token::Dot,
+            '"' => return self.string().map(|token|Some((start,token))),
+            _ => {
+                //'.'后面跟数字时属于浮点数的一部分
+                let bytes = rest.as_bytes();
+                let mut end = 0;
+                while end<bytes.len(){
+                    match bytes[end] {
+                        b'<'|b'>'|b'['|b']'|b'"' => break,
+                        b'.' if !bytes.get(end+1).is_some_and(|byte|byte.is_ascii_digit()) => break,
+                        byte if byte.is_ascii_whitespace() => break,
+                        _ => end += 1,
+                    }
+                }
+                //非ASCII字符不会匹配上面的分隔符，end总在字符边界上
+                self.offset = start+end;
+                return Ok(Some((start,Token::Word(&rest[..end]))));
+            }
+        };
+        self.offset = start+1;
+        Ok(Some((start,token)))
+    }
+
+    fn string(&mut self)->Result<Token<'a>,ParseError>{
+        let start = self.offset;
+        let mut text = String::new();
+        let mut chars = self.text[start+1..].char_indices();
+        while let Some((index,c)) = chars.next(){
+            match c {
+                '"' => {
+                    self.offset = start+1+index+1;
+                    return Ok(Token::Str(text));
+                }
+                '\\' => match chars.next() {
+                    Some((_,'n')) => text.push('\n'),
+                    Some((_,'r')) => text.push('\r'),
+                    Some((_,'t')) => text.push('\t'),
+                    Some((_,c)) => text.push(c),
+                    None => break,
+                },
+                c => text.push(c),
+            }
+        }
+        Err(ParseError::new(self.text,start,ParseErrorKind::UnterminatedString))
+    }
+}
+
+struct Parser<'a>{
+    tokenizer:Tokenizer<'a>,
+    peeked:Option<Option<(usize,Token<'a>)>>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text:&'a str)->Parser<'a>{
+        Parser{tokenizer:Tokenizer{text,offset:0},peeked:None}
+    }
+
+    fn error(&self,offset:usize,kind:ParseErrorKind)->ParseError{
+        ParseError::new(self.tokenizer.text,offset,kind)
+    }
+
+    fn peek(&mut self)->Result<Option<&(usize,Token<'a>)>,ParseError>{
+        if self.peeked.is_none(){
+            self.peeked = Some(self.tokenizer.next()?);
+        }
+        Ok(self.peeked.as_ref().and_then(|token|token.as_ref()))
+    }
+
+    fn next(&mut self)->Result<Option<(usize,Token<'a>)>,ParseError>{
+        match self.peeked.take() {
+            Some(token) => Ok(token),
+            None => self.tokenizer.next(),
+        }
+    }
+
+    //下一个Token，输入结束时报错
+    fn expect(&mut self)->Result<(usize,Token<'a>),ParseError>{
+        match self.next()? {
+            Some(token) => Ok(token),
+            None => Err(self.error(self.tokenizer.text.len(),ParseErrorKind::UnexpectedEof)),
+        }
+    }
+
+    fn end(&mut self)->Result<(),ParseError>{
+        match self.next()? {
+            None => Ok(()),
+            Some((offset,_)) => Err(self.error(offset,ParseErrorKind::UnexpectedToken{expected:"end of input"})),
+        }
+    }
+
+    fn message(&mut self)->Result<SmlMessage,ParseError>{
+        let (offset,token) = self.expect()?;
+        let Token::Word(word) = token else {
+            return Err(self.error(offset,ParseErrorKind::UnexpectedToken{expected:"stream/function"}));
+        };
+        let stream_function = parse_stream_function(word)
+            .ok_or_else(||self.error(offset,ParseErrorKind::InvalidStreamFunction))?;
+        let mut w_bit = false;
+        if let Some((_,Token::Word(word))) = self.peek()? {
+            if word.eq_ignore_ascii_case("W"){
+                w_bit = true;
+                self.next()?;
+            }
+        }
+        let item = match self.peek()? {
+            Some((_,Token::LAngle)) => Some(self.item(0)?),
+            _ => None,
+        };
+        if let Some((_,Token::Dot)) = self.peek()? {
+            self.next()?;
+        }
+        Ok(SmlMessage{stream_function,w_bit,item})
+    }
+
+    fn item(&mut self,depth:usize)->Result<Item,ParseError>{
+        let (offset,token) = self.expect()?;
+        if token!=Token::LAngle{
+            return Err(self.error(offset,ParseErrorKind::UnexpectedToken{expected:"'<'"}));
+        }
+        if depth>=MAX_DEPTH{
+            return Err(self.error(offset,ParseErrorKind::TooDeep));
+        }
+        let (format_offset,token) = self.expect()?;
+        let Token::Word(format) = token else {
+            return Err(self.error(format_offset,ParseErrorKind::UnexpectedToken{expected:"item format"}));
+        };
+        let format_code = parse_format_code(format)
+            .ok_or_else(||self.error(format_offset,ParseErrorKind::UnknownFormat(String::from(format))))?;
+
+        //跳过长度提示 [n]
+        if let Some((_,Token::LBracket)) = self.peek()? {
+            self.next()?;
+            match self.expect()? {
+                (_,Token::Word(_)) => {}
+                (offset,_) => return Err(self.error(offset,ParseErrorKind::UnexpectedToken{expected:"length"})),
+            }
+            match self.expect()? {
+                (_,Token::RBracket) => {}
+                (offset,_) => return Err(self.error(offset,ParseErrorKind::UnexpectedToken{expected:"']'"})),
+            }
+        }
+
+        if format_code==FormatCode::L{
+            let mut items = Vec::new();
+            loop {
+                match self.peek()? {
+                    Some((_,Token::RAngle)) => {
+                        self.next()?;
+                        return Ok(Item::L(items));
+                    }
+                    Some((_,Token::LAngle)) => items.push(self.item(depth+1)?),
+                    Some((offset,_)) => {
+                        let offset = *offset;
+                        return Err(self.error(offset,ParseErrorKind::UnexpectedToken{expected:"'<' or '>'"}));
+                    }
+                    None => return Err(self.error(self.tokenizer.text.len(),ParseErrorKind::UnexpectedEof)),
+                }
+            }
+        }
+
+        let mut item = empty_item(format_code);
+        loop {
+            let (offset,token) = self.expect()?;
+            match (&mut item,token) {
+                (_,Token::RAngle) => return Ok(item),
+                (Item::A(values)|Item::J(values),Token::Str(value)) => values.extend_from_slice(value.as_bytes()),
+                (item,Token::Word(word)) => {
+                    if !push_value(item,word){
+                        return Err(self.error(offset,ParseErrorKind::InvalidValue(format_code)));
+                    }
+                }
+                _ => return Err(self.error(offset,ParseErrorKind::UnexpectedToken{expected:"value or '>'"})),
+            }
+        }
+    }
+}
+
+fn parse_stream_function(word:&str)->Option<StreamFunction>{
+    let rest = word.strip_prefix(['S','s'])?;
+    let index = rest.find(['F','f'])?;
+    let stream:u8 = rest[..index].parse().ok()?;
+    let function:u8 = rest[index+1..].parse().ok()?;
+    //Stream只有7位
+    StreamFunction::new(stream,function)
+}
+
+fn parse_format_code(word:&str)->Option<FormatCode>{
+    let format_code = match word.to_ascii_uppercase().as_str() {
+        "L" => FormatCode::L,
+        "B" => FormatCode::B,
+        "BOOLEAN" => FormatCode::Boolean,
+        "A" => FormatCode::A,
+        "J" => FormatCode::J,
+        "I1" => FormatCode::I1,
+        "I2" => FormatCode::I2,
+        "I4" => FormatCode::I4,
+        "I8" => FormatCode::I8,
+        "U1" => FormatCode::U1,
+        "U2" => FormatCode::U2,
+        "U4" => FormatCode::U4,
+        "U8" => FormatCode::U8,
+        "F4" => FormatCode::F4,
+        "F8" => FormatCode::F8,
+        _ => return None,
+    };
+    Some(format_code)
+}
+
+fn empty_item(format_code:FormatCode)->Item{
+    match format_code {
+        FormatCode::L => Item::L(Vec::new()),
+        FormatCode::B => Item::B(Vec::new()),
+        FormatCode::Boolean => Item::Boolean(Vec::new()),
+        FormatCode::A => Item::A(Vec::new()),
+        FormatCode::J => Item::J(Vec::new()),
+        FormatCode::I1 => Item::I1(Vec::new()),
+        FormatCode::I2 => Item::I2(Vec::new()),
+        FormatCode::I4 => Item::I4(Vec::new()),
+        FormatCode::I8 => Item::I8(Vec::new()),
+        FormatCode::U1 => Item::U1(Vec::new()),
+        FormatCode::U2 => Item::U2(Vec::new()),
+        FormatCode::U4 => Item::U4(Vec::new()),
+        FormatCode::U8 => Item::U8(Vec::new()),
+        FormatCode::F4 => Item::F4(Vec::new()),
+        FormatCode::F8 => Item::F8(Vec::new()),
+    }
+}
+
+//十进制、0x十六进制、0b二进制，可带负号
+fn parse_integer(word:&str)->Option<i128>{
+    let (negative,digits) = match word.strip_prefix('-') {
+        Some(digits) => (true,digits),
+        None => (false,word),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(||digits.strip_prefix("0X")) {
+        u64::from_str_radix(hex,16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(||digits.strip_prefix("0B")) {
+        u64::from_str_radix(binary,2).ok()?
+    } else {
+        digits.parse::<u64>().ok()?
+    } as i128;
+    Some(if negative {-value} else {value})
+}
+
+//值追加到item，格式不匹配或越界返回false
+fn push_value(item:&mut Item,word:&str)->bool{
+    fn integer<T:TryFrom<i128>>(values:&mut Vec<T>,word:&str)->bool{
+        match parse_integer(word).and_then(|value|T::try_from(value).ok()) {
+            Some(value) => {
+                values.push(value);
+                true
+            }
+            None => false,
+        }
+    }
+    match item {
+        Item::L(_) => false,
+        Item::B(values)|Item::A(values)|Item::J(values) => integer(values,word),
+        Item::Boolean(values) => {
+            let value = if word.eq_ignore_ascii_case("TRUE") {
+                true
+            } else if word.eq_ignore_ascii_case("FALSE") {
+                false
+            } else {
+                match parse_integer(word).and_then(|value|u8::try_from(value).ok()) {
+                    Some(value) => value!=0,
+                    None => return false,
+                }
+            };
+            values.push(value);
+            true
+        }
+        Item::I1(values) => integer(values,word),
+        Item::I2(values) => integer(values,word),
+        Item::I4(values) => integer(values,word),
+        Item::I8(values) => integer(values,word),
+        Item::U1(values) => integer(values,word),
+        Item::U2(values) => integer(values,word),
+        Item::U4(values) => integer(values,word),
+        Item::U8(values) => integer(values,word),
+        Item::F4(values) => word.parse().map(|value|values.push(value)).is_ok(),
+        Item::F8(values) => word.parse().map(|value|values.push(value)).is_ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use alloc::vec;
+    use alloc::string::ToString;
+    use crate::hsms::SessionType;
+    use crate::stream_function::S1F13;
+
+    #[test]
+    fn test_parse_message(){
+        let message = parse_message(r#"S1F13 W <L <A "MDLN"> <A "1.0">>."#).unwrap();
+        assert_eq!(message.stream_function,S1F13);
+        assert!(message.w_bit);
+        assert_eq!(message.item,Some(Item::list().ascii("MDLN").ascii("1.0").build()));
+
+        let hsms_message = message.to_hsms_message(Role::Host,DeviceId::new(1).unwrap(),7).unwrap();
+        assert_eq!(hsms_message.hsms_header().stream_function(),Some(S1F13));
+        assert!(hsms_message.hsms_header().w_bit());
+        assert_eq!(hsms_message.decode_body(),Ok(message.item));
+
+        let message:SmlMessage = "s1f1 w".parse().unwrap();
+        assert_eq!(message,SmlMessage{stream_function:StreamFunction(1,1),w_bit:true,item:None});
+        let message:SmlMessage = "S1F2\n<L [0]\n>\n.".parse().unwrap();
+        assert_eq!(message,SmlMessage{stream_function:StreamFunction(1,2),w_bit:false,item:Some(Item::L(vec![]))});
+    }
+
+    #[test]
+    fn test_parse_item(){
+        assert_eq!(parse_item("<U4 [2] 1 0xFFFFFFFF>"),Ok(Item::U4(vec![1,0xFFFF_FFFF])));
+        assert_eq!(parse_item("<I2 -32768 0b11>"),Ok(Item::I2(vec![-32768,3])));
+        assert_eq!(parse_item("<B 0x00 255>"),Ok(Item::B(vec![0x00,0xFF])));
+        assert_eq!(parse_item("<BOOLEAN TRUE false 1 0>"),Ok(Item::Boolean(vec![true,false,true,false])));
+        assert_eq!(parse_item("<F4 1.5 -2>"),Ok(Item::F4(vec![1.5,-2.0])));
+        assert_eq!(parse_item("<F8 1e3>"),Ok(Item::F8(vec![1000.0])));
+        assert_eq!(parse_item(r#"<A "say \"hi\"" "\r\n">"#),Ok(Item::ascii("say \"hi\"\r\n")));
+        assert_eq!(parse_item(r#"<A>"#),Ok(Item::A(vec![])));
+        assert_eq!(parse_item(r#"<A "A" 0x01 0xB0>"#),Ok(Item::A(vec![b'A',0x01,0xB0])));
+        assert_eq!(parse_item(r#"<J "AB">"#),Ok(Item::J(vec![b'A',b'B'])));
+        assert_eq!("<l <u1 1> <L>>".parse::<Item>(),Ok(Item::list().u1(1).item(Item::L(vec![])).build()));
+    }
+
+    #[test]
+    fn test_display(){
+        let item = Item::list()
+            .ascii("MDLN")
+            .list(|l|l.u4(1).boolean(true))
+            .item(Item::L(vec![]))
+            .item(Item::B(vec![0x00,0xFF]))
+            .item(Item::F4(vec![1.5,-2.0]))
+            .item(Item::ascii("a\"b\\\r\n"))
+            .item(Item::A(vec![0x41,0x01,0xB0]))
+            .item(Item::J(vec![0xFF]))
+            .item(Item::I2(vec![]))
+            .build();
+        let text = concat!(
+            "<L [9]\n",
+            "  <A [4] \"MDLN\">\n",
+            "  <L [2]\n",
+            "    <U4 [1] 1>\n",
+            "    <BOOLEAN [1] TRUE>\n",
+            "  >\n",
+            "  <L [0]>\n",
+            "  <B [2] 0x00 0xFF>\n",
+            "  <F4 [2] 1.5 -2>\n",
+            "  <A [6] \"a\\\"b\\\\\\r\\n\">\n",
+            "  <A [3] 0x41 0x01 0xB0>\n",
+            "  <J [1] 0xFF>\n",
+            "  <I2 [0]>\n",
+            ">",
+        );
+        assert_eq!(item.to_string(),text);
+        assert_eq!(parse_item(text),Ok(item.clone()));
+
+        let message = SmlMessage{stream_function:S1F13,w_bit:true,item:Some(Item::list().ascii("MDLN").ascii("1.0").build())};
+        let text = "S1F13 W\n<L [2]\n  <A [4] \"MDLN\">\n  <A [3] \"1.0\">\n>\n.";
+        assert_eq!(message.to_string(),text);
+        assert_eq!(parse_message(text),Ok(message.clone()));
+        assert_eq!(SmlMessage{stream_function:StreamFunction(1,2),w_bit:false,item:None}.to_string(),"S1F2.");
+
+        let hsms_message = message.to_hsms_message(Role::Host,DeviceId::new(1).unwrap(),7).unwrap();
+        assert_eq!(SmlMessage::try_from(&hsms_message),Ok(message));
+        let select_req = HSMSMessage::from_header(HSMSHeader::new(SessionType::SelectReq,0xFFFF,0,0,0,0,0,0,1));
+        assert_eq!(SmlMessage::try_from(&select_req),Err(DecodeError::NotDataMessage(1)));
+    }
+
+    #[test]
+    fn test_parse_errors(){
+        let error = parse_item("<L\n  <U1 256>>").unwrap_err();
+        assert_eq!(error,ParseError{offset:9,line:2,column:7,kind:ParseErrorKind::InvalidValue(FormatCode::U1)});
+        assert_eq!(error.to_string(),"line 2, column 7: Invalid value for U1");
+
+        assert_eq!(parse_item("<X 1>").unwrap_err().kind,ParseErrorKind::UnknownFormat(String::from("X")));
+        assert_eq!(parse_item("<L <A \"x>>").unwrap_err(),
+                   ParseError{offset:6,line:1,column:7,kind:ParseErrorKind::UnterminatedString});
+        assert_eq!(parse_item("<L <U1 1>").unwrap_err().kind,ParseErrorKind::UnexpectedEof);
+        assert_eq!(parse_item("<U1 \"1\">").unwrap_err().kind,ParseErrorKind::UnexpectedToken{expected:"value or '>'"});
+        assert_eq!(parse_item("<U1 1> <U1 2>").unwrap_err().offset,7);
+        assert_eq!(parse_message("S200F1").unwrap_err().kind,ParseErrorKind::InvalidStreamFunction);
+        assert_eq!(parse_message("S1F1 W. <L>").unwrap_err().kind,ParseErrorKind::UnexpectedToken{expected:"end of input"});
+
+        let deep = "<L ".repeat(MAX_DEPTH+1);
+        assert_eq!(parse_item(&deep).unwrap_err().kind,ParseErrorKind::TooDeep);
+    }
+}
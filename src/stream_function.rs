@@ -0,0 +1,169 @@
+use core::fmt;
+
+/*
+ * @brief StreamFunction
+ * SnFm，Stream占7位(0-127)，Function占8位(0-255)
+ * 奇数Function为主消息，偶数Function为回复，Function 0为事务中止
+ * 字段公开，可直接用于模式匹配：
+ *   match sf { S1F13 => ..., StreamFunction(6, _) => ..., _ => ... }
+ * 直接构造时不检查Stream范围，运行时的值请用StreamFunction::new
+ */
+#[derive(Debug,Clone,Copy,Eq, PartialEq,Hash,PartialOrd,Ord)]
+pub struct StreamFunction(pub u8, pub u8);
+
+//Stream只有7位
+pub const MAX_STREAM:u8 = 0x7F;
+
+/*
+ * sf!(2,41) 等价于 StreamFunction(2,41)
+ * 参数须为常量，Stream超过MAX_STREAM时编译失败
+ */
+#[macro_export]
+macro_rules! sf {
+    ($stream:expr, $function:expr) => {
+        {
+            const { assert!($stream<=$crate::stream_function::MAX_STREAM,"stream exceeds 7 bits") };
+            $crate::stream_function::StreamFunction($stream, $function)
+        }
+    };
+}
+
+impl StreamFunction {
+    //Stream超过MAX_STREAM时返回None
+    pub const fn new(stream:u8,function:u8)->Option<StreamFunction>{
+        if stream>MAX_STREAM{
+            return None;
+        }
+        Some(StreamFunction(stream,function))
+    }
+
+    pub fn stream(&self)->u8{
+        self.0
+    }
+
+    pub fn function(&self)->u8{
+        self.1
+    }
+
+    pub fn is_primary(&self)->bool{
+        self.1&0x01==1
+    }
+
+    //主消息对应的回复，回复消息本身返回None；F255没有对应的F256，也返回None
+    pub fn reply(&self)->Option<StreamFunction>{
+        if self.is_primary(){
+            Some(StreamFunction(self.0,self.1.checked_add(1)?))
+        }else{
+            None
+        }
+    }
+
+    //同一Stream的中止消息 SxF0
+    pub fn abort(&self)->StreamFunction{
+        StreamFunction(self.0,0)
+    }
+}
+
+impl fmt::Display for StreamFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f,"S{}F{}",self.0,self.1)
+    }
+}
+
+impl From<(u8,u8)> for StreamFunction {
+    fn from((stream,function): (u8,u8)) -> Self {
+        StreamFunction(stream,function)
+    }
+}
+
+//Stream 1 设备状态
+pub const S1F1:StreamFunction = StreamFunction(1,1);
+pub const S1F2:StreamFunction = StreamFunction(1,2);
+pub const S1F3:StreamFunction = StreamFunction(1,3);
+pub const S1F4:StreamFunction = StreamFunction(1,4);
+pub const S1F13:StreamFunction = StreamFunction(1,13);
+pub const S1F14:StreamFunction = StreamFunction(1,14);
+pub const S1F15:StreamFunction = StreamFunction(1,15);
+pub const S1F16:StreamFunction = StreamFunction(1,16);
+pub const S1F17:StreamFunction = StreamFunction(1,17);
+pub const S1F18:StreamFunction = StreamFunction(1,18);
+//Stream 2 设备控制
+pub const S2F13:StreamFunction = StreamFunction(2,13);
+pub const S2F14:StreamFunction = StreamFunction(2,14);
+pub const S2F15:StreamFunction = StreamFunction(2,15);
+pub const S2F16:StreamFunction = StreamFunction(2,16);
+pub const S2F17:StreamFunction = StreamFunction(2,17);
+pub const S2F18:StreamFunction = StreamFunction(2,18);
+pub const S2F33:StreamFunction = StreamFunction(2,33);
+pub const S2F34:StreamFunction = StreamFunction(2,34);
+pub const S2F35:StreamFunction = StreamFunction(2,35);
+pub const S2F36:StreamFunction = StreamFunction(2,36);
+pub const S2F37:StreamFunction = StreamFunction(2,37);
+pub const S2F38:StreamFunction = StreamFunction(2,38);
+pub const S2F41:StreamFunction = StreamFunction(2,41);
+pub const S2F42:StreamFunction = StreamFunction(2,42);
+//Stream 5 报警
+pub const S5F1:StreamFunction = StreamFunction(5,1);
+pub const S5F2:StreamFunction = StreamFunction(5,2);
+pub const S5F3:StreamFunction = StreamFunction(5,3);
+pub const S5F4:StreamFunction = StreamFunction(5,4);
+//Stream 6 数据收集
+pub const S6F11:StreamFunction = StreamFunction(6,11);
+pub const S6F12:StreamFunction = StreamFunction(6,12);
+//Stream 7 程式管理
+pub const S7F3:StreamFunction = StreamFunction(7,3);
+pub const S7F4:StreamFunction = StreamFunction(7,4);
+pub const S7F5:StreamFunction = StreamFunction(7,5);
+pub const S7F6:StreamFunction = StreamFunction(7,6);
+//Stream 9 系统错误
+pub const S9F1:StreamFunction = StreamFunction(9,1);
+pub const S9F3:StreamFunction = StreamFunction(9,3);
+pub const S9F5:StreamFunction = StreamFunction(9,5);
+pub const S9F7:StreamFunction = StreamFunction(9,7);
+pub const S9F9:StreamFunction = StreamFunction(9,9);
+pub const S9F11:StreamFunction = StreamFunction(9,11);
+//Stream 10 终端服务
+pub const S10F3:StreamFunction = StreamFunction(10,3);
+pub const S10F4:StreamFunction = StreamFunction(10,4);
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test]
+    fn test_sf_macro(){
+        assert_eq!(sf!(2,41),S2F41);
+        assert_eq!(sf!(2,41).to_string(),"S2F41");
+        assert_eq!(sf!(MAX_STREAM,1),StreamFunction(0x7F,1));
+    }
+
+    #[test]
+    fn test_new(){
+        assert_eq!(StreamFunction::new(2,41),Some(S2F41));
+        assert_eq!(StreamFunction::new(0x7F,0),Some(StreamFunction(0x7F,0)));
+        assert_eq!(StreamFunction::new(0x80,1),None);
+    }
+
+    #[test]
+    fn test_reply(){
+        assert_eq!(S1F13.reply(),Some(S1F14));
+        assert_eq!(S1F14.reply(),None);
+        assert_eq!(StreamFunction(1,255).reply(),None);
+        assert_eq!(S6F11.abort(),StreamFunction(6,0));
+    }
+
+    #[test]
+    fn test_pattern_matching(){
+        fn name(stream_function:StreamFunction)->&'static str{
+            match stream_function {
+                S1F13 => "establish communication",
+                StreamFunction(6,_) => "data collection",
+                _ => "other",
+            }
+        }
+        assert_eq!(name(sf!(1,13)),"establish communication");
+        assert_eq!(name(S6F11),"data collection");
+        assert_eq!(name(S2F41),"other");
+    }
+}
@@ -11,6 +11,30 @@ pub enum Error {
 
     #[error("{0}")]
     Connection(String),
+
+    #[error("{0}")]
+    Frame(&'static str),
+
+    #[error("{0}")]
+    InvalidFrame(crate::hsms::FrameError),
+
+    #[error(transparent)]
+    Encode(#[from] crate::secs2::EncodeError),
+
+    #[error("Message length {0} exceeds limit {1}")]
+    MessageTooLong(u32,u32),
+
+    #[error("Not connected")]
+    NotConnected,
+
+    #[error("Not selected")]
+    NotSelected,
+
+    #[error("Select rejected with status {0}")]
+    SelectRejected(u8),
+
+    #[error("{0} timeout")]
+    Timeout(&'static str),
 }
 
 // manually implement serde::Serialize
@@ -1,10 +1,25 @@
 pub use crate::utils::Error;
+use bincode::Options;
 use tokio::io::{AsyncBufRead, AsyncReadExt};
-pub fn serialize<S>(data: &S) -> Vec<u8>
+
+/*
+ * bincode默认为小端，不符合E37的网络字节序
+ * 这里固定为大端、定长整数编码，serde结构体的输出与wire模块一致
+ * 允许尾部多余字节，与bincode::deserialize的行为保持一致
+ */
+fn options()->impl Options{
+    bincode::DefaultOptions::new()
+        .with_big_endian()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+}
+
+pub fn serialize<S>(data: &S) -> Result<Vec<u8>,Error>
     where
         S: serde::Serialize + ?Sized,
 {
-    bincode::serialize(data).unwrap()
+    let bytes = options().serialize(data)?;
+    Ok(bytes)
 }
 
 pub async fn deserialize<T, U>(buff_reader: &mut T) -> Result<U,Error>
@@ -14,7 +29,7 @@ pub async fn deserialize<T, U>(buff_reader: &mut T) -> Result<U,Error>
 {
     let mut content:Vec<u8> = Vec::new();
     buff_reader.read_to_end(&mut content).await?;
-    let data: U = bincode::deserialize(&content)?;
+    let data: U = options().deserialize(&content)?;
     Ok(data)
 }
 
@@ -22,6 +37,6 @@ pub fn deserialize_from_bytes<U>(bytes: &[u8]) -> Result<U,Error>
     where
         U: serde::de::DeserializeOwned,
 {
-    let data: U = bincode::deserialize(&bytes)?;
+    let data: U = options().deserialize(bytes)?;
     Ok(data)
 }
\ No newline at end of file
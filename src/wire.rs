@@ -0,0 +1,170 @@
+use alloc::vec::Vec;
+
+/*
+ * @brief 线上编码
+ * E37和E5规定所有多字节字段都按网络字节序（大端）传输：
+ *   长度前缀   4字节 u32
+ *   消息头     Session ID u16、System Bytes u32
+ *   数值Item   I2/I4/I8/U2/U4/U8/F4/F8 的每个元素
+ * 所有大端读写都经过这里，不依赖serde/bincode的默认字节序（小端）
+ */
+
+//长度前缀字节数
+pub const LENGTH_PREFIX_LEN:usize = 4;
+//消息头字节数
+pub const HEADER_LEN:usize = 10;
+
+pub trait BigEndian:Sized+Copy{
+    const SIZE:usize;
+
+    fn write_be(self,buf:&mut Vec<u8>);
+
+    //bytes不足SIZE字节返回None，多余的字节忽略
+    fn read_be(bytes:&[u8])->Option<Self>;
+}
+
+macro_rules! impl_big_endian {
+    ($($t:ty),*) => {
+        $(
+            impl BigEndian for $t {
+                const SIZE:usize = core::mem::size_of::<$t>();
+
+                fn write_be(self,buf:&mut Vec<u8>){
+                    buf.extend_from_slice(&self.to_be_bytes());
+                }
+
+                fn read_be(bytes:&[u8])->Option<Self>{
+                    let (bytes,_) = bytes.split_first_chunk::<{core::mem::size_of::<$t>()}>()?;
+                    Some(<$t>::from_be_bytes(*bytes))
+                }
+            }
+        )*
+    };
+}
+
+impl_big_endian!(u8,u16,u32,u64,i8,i16,i32,i64,f32,f64);
+
+pub fn write_values<T:BigEndian>(values:&[T],buf:&mut Vec<u8>){
+    buf.reserve(values.len()*T::SIZE);
+    values.iter().for_each(|value|value.write_be(buf));
+}
+
+//data长度不是SIZE的整数倍时，末尾不完整的元素被忽略
+pub fn read_values<T:BigEndian>(data:&[u8])->Vec<T>{
+    data.chunks_exact(T::SIZE).filter_map(T::read_be).collect()
+}
+
+pub fn encode_length(message_length:u32)->[u8;LENGTH_PREFIX_LEN]{
+    message_length.to_be_bytes()
+}
+
+pub fn decode_length(bytes:&[u8;LENGTH_PREFIX_LEN])->u32{
+    u32::from_be_bytes(*bytes)
+}
+
+//消息头：Session ID（2字节）、中间4个单字节字段（Header Byte 2/3、PType、SType）、System Bytes（4字节）
+pub fn encode_header(session_id:u16,fields:[u8;4],system_bytes:u32)->[u8;HEADER_LEN]{
+    let mut header = [0u8;HEADER_LEN];
+    header[0..2].copy_from_slice(&session_id.to_be_bytes());
+    header[2..6].copy_from_slice(&fields);
+    header[6..10].copy_from_slice(&system_bytes.to_be_bytes());
+    header
+}
+
+pub fn decode_header(bytes:&[u8;HEADER_LEN])->(u16,[u8;4],u32){
+    let session_id = u16::from_be_bytes([bytes[0],bytes[1]]);
+    let fields = [bytes[2],bytes[3],bytes[4],bytes[5]];
+    let system_bytes = u32::from_be_bytes([bytes[6],bytes[7],bytes[8],bytes[9]]);
+    (session_id,fields,system_bytes)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use alloc::vec;
+    use crate::hsms::{HSMSMessage, SessionType};
+    use crate::secs2::Item;
+    use crate::stream_function::{S1F13, S2F33};
+
+    #[test]
+    fn test_big_endian_values(){
+        let mut buf = Vec::new();
+        0x0102u16.write_be(&mut buf);
+        (-2i32).write_be(&mut buf);
+        1.0f32.write_be(&mut buf);
+        assert_eq!(buf,vec![0x01,0x02,0xFF,0xFF,0xFF,0xFE,0x3F,0x80,0x00,0x00]);
+        assert_eq!(u16::read_be(&buf),Some(0x0102));
+        assert_eq!(i32::read_be(&buf[2..]),Some(-2));
+        assert_eq!(f32::read_be(&buf[6..]),Some(1.0));
+        assert_eq!(u64::read_be(&buf[6..]),None);
+
+        let mut buf = Vec::new();
+        write_values(&[1u64,u64::MAX],&mut buf);
+        assert_eq!(buf,vec![0,0,0,0,0,0,0,1,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF,0xFF]);
+        assert_eq!(read_values::<u64>(&buf),vec![1,u64::MAX]);
+        assert_eq!(read_values::<f64>(&buf[..8]),vec![f64::from_bits(1)]);
+
+        assert_eq!(encode_length(0x0102_0304),[0x01,0x02,0x03,0x04]);
+        assert_eq!(decode_length(&[0x00,0x00,0x01,0x00]),256);
+
+        let header = encode_header(0x8001,[0x81,0x0D,0x00,0x00],0x0102_0304);
+        assert_eq!(header,[0x80,0x01,0x81,0x0D,0x00,0x00,0x01,0x02,0x03,0x04]);
+        assert_eq!(decode_header(&header),(0x8001,[0x81,0x0D,0x00,0x00],0x0102_0304));
+    }
+
+    //以下为按E37/E5逐字节核对的参考帧，编码后必须与原始字节完全一致
+    #[test]
+    fn test_reference_frame_linktest_req(){
+        let frame:Vec<u8> = vec![0x00,0x00,0x00,0x0A,0xFF,0xFF,0x00,0x00,0x00,0x05,0x00,0x00,0x00,0x2A];
+        let hsms_message = HSMSMessage::from_bytes(frame.clone()).unwrap();
+        let hsms_header = hsms_message.hsms_header();
+        assert_eq!(hsms_header.get_session_type(),Ok(SessionType::LinktestReq));
+        assert_eq!(hsms_header.session_id().value(),0xFFFF);
+        assert_eq!(hsms_message.to_bytes(),frame);
+    }
+
+    #[test]
+    fn test_reference_frame_s1f13(){
+        //S1F13 W <L [2] <A "MDLN"> <A "1.0">>，Session ID 1，System Bytes 0x00000102
+        let frame:Vec<u8> = vec![
+            0x00,0x00,0x00,0x17,
+            0x00,0x01,0x81,0x0D,0x00,0x00,0x00,0x00,0x01,0x02,
+            0x01,0x02,0x41,0x04,b'M',b'D',b'L',b'N',0x41,0x03,b'1',b'.',b'0',
+        ];
+        let hsms_message = HSMSMessage::from_bytes(frame.clone()).unwrap();
+        let hsms_header = hsms_message.hsms_header();
+        assert_eq!(hsms_header.stream_function(),Some(S1F13));
+        assert!(hsms_header.w_bit());
+        assert_eq!(hsms_header.session_id().value(),1);
+        assert_eq!(hsms_header.to_array()[6..],[0x00,0x00,0x01,0x02]);
+        assert_eq!(hsms_message.decode_body(),Ok(Some(Item::list().ascii("MDLN").ascii("1.0").build())));
+        assert_eq!(hsms_message.to_bytes(),frame);
+    }
+
+    #[test]
+    fn test_reference_frame_s2f33_numeric_items(){
+        //S2F33 W <L [2] <U4 1> <L [1] <L [2] <U4 1000> <L [2] <U4 0x01020304> <I2 -1>>>>>
+        let frame:Vec<u8> = vec![
+            0x00,0x00,0x00,0x28,
+            0x00,0x01,0x82,0x21,0x00,0x00,0xDE,0xAD,0xBE,0xEF,
+            0x01,0x02,
+            0xB1,0x04,0x00,0x00,0x00,0x01,
+            0x01,0x01,
+            0x01,0x02,
+            0xB1,0x04,0x00,0x00,0x03,0xE8,
+            0x01,0x02,
+            0xB1,0x04,0x01,0x02,0x03,0x04,
+            0x69,0x02,0xFF,0xFF,
+        ];
+        let hsms_message = HSMSMessage::from_bytes(frame.clone()).unwrap();
+        assert_eq!(hsms_message.hsms_header().stream_function(),Some(S2F33));
+        assert_eq!(hsms_message.hsms_header().to_array()[6..],[0xDE,0xAD,0xBE,0xEF]);
+        let item = Item::list()
+            .u4(1)
+            .list(|l|l.list(|l|l.u4(1000).list(|l|l.u4(0x0102_0304).i2(-1))))
+            .build();
+        assert_eq!(hsms_message.decode_body(),Ok(Some(item.clone())));
+        let hsms_message = HSMSMessage::encode_body(hsms_message.hsms_header().clone(),&item).unwrap();
+        assert_eq!(hsms_message.to_bytes(),frame);
+    }
+}